@@ -0,0 +1,77 @@
+use sha2::{Digest, Sha256};
+
+use crate::graph_disc::DiscriminatorKind;
+
+// Namespaces Anchor hashes names under to derive discriminators
+pub const INSTRUCTION_NAMESPACE: &str = "global";
+pub const ACCOUNT_NAMESPACE: &str = "account";
+pub const EVENT_NAMESPACE: &str = "event";
+
+// Function to derive an Anchor discriminator: the first 8 bytes of sha256("<namespace>:<name>")
+pub fn sighash(namespace: &str, name: &str) -> [u8; 8] {
+    let mut hasher = Sha256::new();
+    hasher.update(format!("{}:{}", namespace, name));
+    let hash = hasher.finalize();
+
+    let mut discriminator = [0u8; 8];
+    discriminator.copy_from_slice(&hash[..8]);
+    discriminator
+}
+
+// Function to derive the discriminator of an instruction from the name Anchor logs for it.
+// Anchor logs the PascalCase name but hashes the snake_case handler name.
+pub fn instruction_discriminator(name: &str) -> [u8; 8] {
+    sighash(INSTRUCTION_NAMESPACE, &to_snake_case(name))
+}
+
+// Function to derive the discriminator Anchor would assign to a name of the given kind.
+// Account and event names are hashed exactly as written, which is the PascalCase type name.
+pub fn discriminator_for(kind: DiscriminatorKind, name: &str) -> [u8; 8] {
+    match kind {
+        DiscriminatorKind::Instruction => instruction_discriminator(name),
+        DiscriminatorKind::Account => sighash(ACCOUNT_NAMESPACE, name),
+        DiscriminatorKind::Event => sighash(EVENT_NAMESPACE, name),
+    }
+}
+
+// Function to convert a PascalCase or camelCase identifier to snake_case
+pub fn to_snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut snake = String::with_capacity(name.len() + 4);
+
+    for (i, c) in chars.iter().enumerate() {
+        if c.is_uppercase() {
+            let previous = i.checked_sub(1).map(|p| chars[p]);
+            let next = chars.get(i + 1);
+            // Word boundary after a lowercase letter or digit, or at the end of an acronym ("HTTPServer")
+            let boundary = match previous {
+                Some(p) if p.is_lowercase() || p.is_ascii_digit() => true,
+                Some(p) if p.is_uppercase() => next.is_some_and(|n| n.is_lowercase()),
+                _ => false,
+            };
+            if boundary && !snake.ends_with('_') {
+                snake.push('_');
+            }
+            snake.extend(c.to_lowercase());
+        } else {
+            snake.push(*c);
+        }
+    }
+
+    snake
+}
+
+
+// Function to convert a snake_case identifier to PascalCase, the form Anchor uses for account and event names
+pub fn to_pascal_case(name: &str) -> String {
+    name.split('_')
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect::<String>(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,300 @@
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue, RETRY_AFTER};
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpMessage, HttpRequest, HttpResponse, Responder};
+use log::error;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::auth::{random_hex, AuthenticatedUser};
+use crate::graph_disc::{unix_timestamp, ApiKey, GraphDatabase};
+use crate::rate_limit::{ClientIdentity, RateLimitStatus, RequestClass, RequestLimiter, RequestQuotas};
+
+// Header integrations send their key in
+const API_KEY_HEADER: &str = "X-API-Key";
+// Prefix of issued keys, so leaked keys are easy to recognise
+const API_KEY_PREFIX: &str = "ddk_";
+// How long a key lookup is reused before the store is asked again; bounds how late a revocation takes effect elsewhere
+const KEY_CACHE_TTL: Duration = Duration::from_secs(60);
+const MAX_KEYS_PER_USER: usize = 20;
+
+// Routes, by method and pattern, that always make RPC calls and are charged against the cache-miss budget
+const RPC_ROUTES: &[(&str, &str)] = &[
+    ("GET", "/query_discriminators/{program_id}/accounts/{discriminator}"),
+    ("POST", "/analyze/{program_id}"),
+    ("POST", "/decode/transaction"),
+    ("GET", "/decode/account/{address}"),
+    ("POST", "/decode/accounts"),
+    ("POST", "/programs/{program_id}/claim"),
+    ("POST", "/programs/{program_id}/claim/challenge"),
+    ("POST", "/programs/{program_id}/official"),
+    ("POST", "/programs/{program_id}/idl/fetch"),
+];
+
+// The client a request was counted against, stored in the request extensions by the middleware
+#[derive(Debug, Clone)]
+pub struct RequestClient {
+    pub identity: ClientIdentity,
+    pub quotas: RequestQuotas,
+    // Owner of the API key the request was made with
+    pub user_id: Option<String>,
+}
+
+// Resolves API keys to their documents, caching lookups of active keys for a short while.
+// Unknown keys are not cached, so requests with made-up keys cannot grow the cache.
+pub struct ApiKeyAuthenticator {
+    db: Arc<GraphDatabase>,
+    cache: Mutex<HashMap<String, (ApiKey, Instant)>>,
+}
+
+impl ApiKeyAuthenticator {
+    pub fn new(db: Arc<GraphDatabase>) -> Self {
+        ApiKeyAuthenticator {
+            db,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Function to get the active key for a secret, or `None` if it is unknown or revoked
+    async fn authenticate(&self, secret: &str) -> Result<Option<ApiKey>, String> {
+        let key_hash = GraphDatabase::hash_secret(secret);
+
+        if let Some((api_key, fetched)) = self.cache.lock().unwrap().get(&key_hash) {
+            if fetched.elapsed() < KEY_CACHE_TTL {
+                return Ok(Some(api_key.clone()));
+            }
+        }
+
+        let api_key = self
+            .db
+            .get_api_key_by_hash(&key_hash)
+            .await
+            .map_err(|e| e.to_string())?
+            .filter(|api_key| api_key.revoked_at.is_none());
+
+        let mut cache = self.cache.lock().unwrap();
+        cache.retain(|_, (_, fetched)| fetched.elapsed() < KEY_CACHE_TTL);
+        match &api_key {
+            Some(api_key) => cache.insert(key_hash, (api_key.clone(), Instant::now())),
+            None => cache.remove(&key_hash),
+        };
+        Ok(api_key)
+    }
+
+    // Function to drop a revoked key from the cache of this process
+    fn forget(&self, key_hash: &str) {
+        self.cache.lock().unwrap().remove(key_hash);
+    }
+}
+
+// Function to pick the budget a request is charged against from its route and method
+fn request_class(req: &ServiceRequest) -> RequestClass {
+    let method = req.method().as_str();
+    let is_rpc_route = req
+        .match_pattern()
+        .is_some_and(|pattern| RPC_ROUTES.contains(&(method, pattern.as_str())));
+
+    if is_rpc_route {
+        RequestClass::CacheMiss
+    } else if req.method().is_safe() {
+        RequestClass::Read
+    } else {
+        RequestClass::Write
+    }
+}
+
+// Function to build a `429 Too Many Requests` response with a `Retry-After` header in whole seconds
+fn too_many_requests(class: RequestClass, limit: u32, wait: Duration) -> HttpResponse {
+    let retry_after = wait.as_secs_f64().ceil().max(1.0) as u64;
+    HttpResponse::TooManyRequests()
+        .insert_header((RETRY_AFTER, retry_after.to_string()))
+        .insert_header(("X-RateLimit-Limit", limit.to_string()))
+        .insert_header(("X-RateLimit-Remaining", "0"))
+        .json(json!({
+            "error": "Rate limit exceeded",
+            "budget": class.as_str(),
+            "limit_per_minute": limit,
+            "retry_after": retry_after,
+        }))
+}
+
+// Middleware that authenticates the `X-API-Key` header and enforces request rates.
+// Requests with a key are limited by the key's quotas; requests without one by the anonymous quotas of their IP address.
+pub async fn rate_limit_middleware<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, Error> {
+    let (Some(limiter), Some(authenticator)) = (
+        req.app_data::<web::Data<RequestLimiter>>().cloned(),
+        req.app_data::<web::Data<ApiKeyAuthenticator>>().cloned(),
+    ) else {
+        return next.call(req).await.map(ServiceResponse::map_into_left_body);
+    };
+
+    let secret = req
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+
+    let client = match secret {
+        Some(secret) => match authenticator.authenticate(&secret).await {
+            Ok(Some(api_key)) => RequestClient {
+                identity: ClientIdentity::ApiKey(api_key._key),
+                quotas: api_key.quotas,
+                user_id: Some(api_key.user_id),
+            },
+            Ok(None) => {
+                let response = HttpResponse::Unauthorized().json(json!({"error": "Invalid or revoked API key"}));
+                return Ok(req.into_response(response).map_into_right_body());
+            }
+            Err(e) => {
+                error!("Error looking up API key: {}", e);
+                let response = HttpResponse::InternalServerError().json(json!({"error": "Failed to check API key"}));
+                return Ok(req.into_response(response).map_into_right_body());
+            }
+        },
+        None => RequestClient {
+            identity: ClientIdentity::Ip(req.peer_addr().map(|addr| addr.ip().to_string()).unwrap_or_default()),
+            quotas: RequestQuotas::ANONYMOUS,
+            user_id: None,
+        },
+    };
+
+    let class = request_class(&req);
+    let status = match limiter.check(&client.identity, class, &client.quotas) {
+        Ok(status) => status,
+        Err(wait) => {
+            let response = too_many_requests(class, client.quotas.per_minute(class), wait);
+            return Ok(req.into_response(response).map_into_right_body());
+        }
+    };
+
+    req.extensions_mut().insert(client);
+    let mut response = next.call(req).await?;
+    insert_rate_limit_headers(&mut response, status);
+    Ok(response.map_into_left_body())
+}
+
+fn insert_rate_limit_headers<B>(response: &mut ServiceResponse<B>, status: RateLimitStatus) {
+    let headers = response.headers_mut();
+    headers.insert(HeaderName::from_static("x-ratelimit-limit"), HeaderValue::from(status.limit));
+    headers.insert(HeaderName::from_static("x-ratelimit-remaining"), HeaderValue::from(status.remaining));
+}
+
+// A request refused by its cache-miss budget
+#[derive(Debug, Clone, Copy)]
+pub struct CacheMissLimited {
+    limit: u32,
+    wait: Duration,
+}
+
+impl From<CacheMissLimited> for HttpResponse {
+    fn from(limited: CacheMissLimited) -> Self {
+        too_many_requests(RequestClass::CacheMiss, limited.limit, limited.wait)
+    }
+}
+
+// Function to charge a request that turned out to need RPC calls against the cache-miss budget.
+// Used by routes that only sometimes reach the chain, which the middleware cannot classify up front.
+// Handlers turn the error into a `429 Too Many Requests` response with `.into()`.
+pub fn charge_cache_miss(req: &HttpRequest) -> Result<(), CacheMissLimited> {
+    let Some(limiter) = req.app_data::<web::Data<RequestLimiter>>() else {
+        return Ok(());
+    };
+    let Some(client) = req.extensions().get::<RequestClient>().cloned() else {
+        return Ok(());
+    };
+
+    limiter
+        .check(&client.identity, RequestClass::CacheMiss, &client.quotas)
+        .map(|_| ())
+        .map_err(|wait| CacheMissLimited { limit: client.quotas.cache_misses_per_minute, wait })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateApiKeyRequest {
+    name: String,
+}
+
+// Function to render a key without its hash
+fn api_key_json(api_key: &ApiKey) -> serde_json::Value {
+    json!({
+        "id": api_key._key,
+        "name": api_key.name,
+        "quotas": api_key.quotas,
+        "created_at": api_key.created_at,
+        "revoked_at": api_key.revoked_at,
+    })
+}
+
+// Issues a new API key for the signed-in user. The key itself is only ever returned here.
+pub async fn create_api_key_endpoint(
+    db: web::Data<GraphDatabase>,
+    user: AuthenticatedUser,
+    request: web::Json<CreateApiKeyRequest>,
+) -> impl Responder {
+    let name = request.into_inner().name.trim().to_string();
+    if name.is_empty() || name.len() > 64 {
+        return HttpResponse::BadRequest().json(json!({"error": "name must be between 1 and 64 bytes"}));
+    }
+
+    match db.list_api_keys(&user.user_id).await {
+        Ok(keys) if keys.iter().filter(|key| key.revoked_at.is_none()).count() >= MAX_KEYS_PER_USER => {
+            return HttpResponse::Conflict().json(json!({
+                "error": format!("At most {} active API keys are allowed per user", MAX_KEYS_PER_USER),
+            }))
+        }
+        Ok(_) => {}
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+
+    let secret = format!("{}{}", API_KEY_PREFIX, random_hex(32));
+    let api_key = ApiKey {
+        _key: random_hex(8),
+        key_hash: GraphDatabase::hash_secret(&secret),
+        user_id: user.user_id,
+        name,
+        quotas: RequestQuotas::API_KEY,
+        created_at: unix_timestamp(),
+        revoked_at: None,
+    };
+
+    if let Err(e) = db.create_api_key(&api_key).await {
+        error!("Error storing API key: {}", e);
+        return HttpResponse::InternalServerError().json(json!({"error": "Failed to create API key"}));
+    }
+
+    let mut body = api_key_json(&api_key);
+    body["key"] = json!(secret);
+    HttpResponse::Created().json(body)
+}
+
+// Lists the signed-in user's API keys
+pub async fn list_api_keys_endpoint(db: web::Data<GraphDatabase>, user: AuthenticatedUser) -> impl Responder {
+    match db.list_api_keys(&user.user_id).await {
+        Ok(keys) => HttpResponse::Ok().json(keys.iter().map(api_key_json).collect::<Vec<_>>()),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+// Revokes one of the signed-in user's API keys
+pub async fn revoke_api_key_endpoint(
+    db: web::Data<GraphDatabase>,
+    authenticator: web::Data<ApiKeyAuthenticator>,
+    user: AuthenticatedUser,
+    id: web::Path<String>,
+) -> impl Responder {
+    match db.revoke_api_key(&id, &user.user_id).await {
+        Ok(Some(api_key)) => {
+            authenticator.forget(&api_key.key_hash);
+            HttpResponse::Ok().json(api_key_json(&api_key))
+        }
+        Ok(None) => HttpResponse::NotFound().json(json!({"error": "No active API key with this id"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
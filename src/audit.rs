@@ -0,0 +1,174 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue, CONTENT_DISPOSITION};
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpResponse, Responder};
+use futures::stream;
+use log::error;
+use serde::Deserialize;
+use serde_json::json;
+use std::future::Future;
+use tokio_util::io::ReaderStream;
+
+use crate::auth::{random_hex, AuthenticatedUser};
+use crate::graph_disc::{AuditFilter, GraphDatabase, JobKind, JobSpec, JobState, Role};
+use crate::jobs::{audit_export_path, job_accepted, JobRunner};
+
+const DEFAULT_AUDIT_PAGE_SIZE: usize = 100;
+const MAX_AUDIT_PAGE_SIZE: usize = 1000;
+// Events fetched per query while streaming an export
+const EXPORT_BATCH_SIZE: usize = 1000;
+
+tokio::task_local! {
+    // Id of the request being handled, set for the duration of the handler by `request_id_middleware`
+    static REQUEST_ID: String;
+}
+
+// Function to get the id of the request the current task is handling, if any
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(String::clone).ok()
+}
+
+// Function to run a future under a request id, so background work a request starts is audited under it
+pub async fn with_request_id<F: Future>(request_id: Option<String>, future: F) -> F::Output {
+    match request_id {
+        Some(request_id) => REQUEST_ID.scope(request_id, future).await,
+        None => future.await,
+    }
+}
+
+// Middleware that gives every request an id, returned in the `X-Request-Id` header and recorded
+// with the audit events of the writes the request makes
+pub async fn request_id_middleware<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<B>, Error> {
+    let request_id = random_hex(8);
+    let mut response = REQUEST_ID.scope(request_id.clone(), next.call(req)).await?;
+
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(HeaderName::from_static("x-request-id"), value);
+    }
+    Ok(response)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuditPage {
+    limit: Option<usize>,
+}
+
+// Lists audit events, newest first. Takes the filters of `AuditFilter` as query parameters;
+// the returned `next_cursor` is passed as `cursor` to get the next page.
+pub async fn list_audit_events_endpoint(
+    db: web::Data<GraphDatabase>,
+    user: AuthenticatedUser,
+    filter: web::Query<AuditFilter>,
+    page: web::Query<AuditPage>,
+) -> impl Responder {
+    if let Err(denied) = user.require(Role::Moderator) {
+        return denied.into();
+    }
+    let limit = page.limit.unwrap_or(DEFAULT_AUDIT_PAGE_SIZE).clamp(1, MAX_AUDIT_PAGE_SIZE);
+
+    match db.list_audit_events(&filter, false, limit).await {
+        Ok(events) => {
+            let next_cursor = (events.len() == limit).then(|| events.last().map(|event| event._key.clone())).flatten();
+            HttpResponse::Ok().json(json!({"events": events, "next_cursor": next_cursor}))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+// Exports every audit event matching the filters, oldest first, as newline delimited JSON.
+// The log is streamed in batches, so an export of any size holds only one batch in memory.
+pub async fn export_audit_events_endpoint(
+    db: web::Data<GraphDatabase>,
+    user: AuthenticatedUser,
+    filter: web::Query<AuditFilter>,
+) -> impl Responder {
+    if let Err(denied) = user.require(Role::Admin) {
+        return denied.into();
+    }
+
+    let batches = stream::unfold(Some(filter.into_inner()), move |filter| {
+        let db = db.clone();
+        async move {
+            let mut filter = filter?;
+            let events = match db.list_audit_events(&filter, true, EXPORT_BATCH_SIZE).await {
+                Ok(events) if events.is_empty() => return None,
+                Ok(events) => events,
+                Err(e) => {
+                    // Ending the stream with an error aborts the response, so a failed export is never mistaken for a complete one
+                    error!("Error exporting audit log: {}", e);
+                    return Some((Err(actix_web::error::ErrorInternalServerError("Failed to export audit log")), None));
+                }
+            };
+
+            let mut lines = Vec::new();
+            for event in &events {
+                if let Ok(line) = serde_json::to_vec(event) {
+                    lines.extend(line);
+                    lines.push(b'\n');
+                }
+            }
+
+            filter.cursor = events.last().map(|event| event._key.clone());
+            let next = (events.len() == EXPORT_BATCH_SIZE).then_some(filter);
+            Some((Ok::<_, Error>(web::Bytes::from(lines)), next))
+        }
+    });
+
+    HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .insert_header((CONTENT_DISPOSITION, "attachment; filename=\"audit.ndjson\""))
+        .streaming(batches)
+}
+
+// Queues an export of the audit events matching the filter in the body to a file,
+// for exports too large to stream in one request
+pub async fn create_audit_export_endpoint(
+    jobs: web::Data<JobRunner>,
+    user: AuthenticatedUser,
+    filter: web::Json<AuditFilter>,
+) -> impl Responder {
+    if let Err(denied) = user.require(Role::Admin) {
+        return denied.into();
+    }
+
+    match jobs.enqueue(JobSpec::AuditExport { filter: filter.into_inner() }, Some(user.user_id)).await {
+        Ok((job, added)) => job_accepted(&job, added),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+// Downloads the file written by a finished audit export job
+pub async fn download_audit_export_endpoint(
+    db: web::Data<GraphDatabase>,
+    user: AuthenticatedUser,
+    id: web::Path<String>,
+) -> impl Responder {
+    if let Err(denied) = user.require(Role::Admin) {
+        return denied.into();
+    }
+
+    let job = match db.get_job(&id).await {
+        Ok(Some(job)) if job.kind == JobKind::AuditExport => job,
+        Ok(_) => return HttpResponse::NotFound().json(json!({"error": "Export not found or expired"})),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    };
+    if job.state != JobState::Succeeded {
+        return HttpResponse::Conflict().json(json!({"error": "Export has not finished", "state": job.state}));
+    }
+
+    // Streamed from disk, since exports can be far larger than a response should be held in memory
+    match tokio::fs::File::open(audit_export_path(&job._key)).await {
+        Ok(file) => HttpResponse::Ok()
+            .content_type("application/x-ndjson")
+            .insert_header((CONTENT_DISPOSITION, format!("attachment; filename=\"audit-{}.ndjson\"", job._key)))
+            .streaming(ReaderStream::new(file)),
+        Err(e) => {
+            error!("Error opening audit export {}: {}", job._key, e);
+            HttpResponse::NotFound().json(json!({"error": "Export file is missing"}))
+        }
+    }
+}
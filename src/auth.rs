@@ -0,0 +1,276 @@
+use actix_web::dev::Payload;
+use actix_web::error::InternalError;
+use actix_web::{web, FromRequest, HttpMessage, HttpRequest, HttpResponse, Responder};
+use futures::future::LocalBoxFuture;
+use log::error;
+use rand::RngCore;
+use serde::Deserialize;
+use serde_json::json;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::api_keys::RequestClient;
+use crate::graph_disc::{unix_timestamp, GraphDatabase, Role};
+
+// How long a sign-in challenge can be answered
+pub const CHALLENGE_TTL: Duration = Duration::from_secs(300);
+// How long a session token stays valid
+const SESSION_TTL_SECS: u64 = 24 * 60 * 60;
+// First line of the message wallets sign to sign in
+const SIGN_IN_STATEMENT: &str = "Sign in to the Discriminator Directory";
+// Upper bound on outstanding challenges, so unanswered requests cannot grow memory without limit
+const MAX_PENDING_CHALLENGES: usize = 10_000;
+
+// A message a wallet was asked to sign, valid for a single sign-in
+struct Challenge {
+    pubkey: String,
+    // What signing the message grants, so a challenge issued for one purpose cannot be used for another
+    statement: String,
+    message: String,
+    issued: Instant,
+}
+
+// Outstanding challenges by nonce. Each nonce can be used once, and only before it expires.
+pub struct SignInChallenges {
+    pending: Mutex<HashMap<String, Challenge>>,
+}
+
+impl SignInChallenges {
+    pub fn new() -> Self {
+        SignInChallenges {
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Function to issue a new challenge for a wallet and return its nonce and the message to sign
+    pub fn issue(&self, pubkey: &str, statement: &str) -> (String, String) {
+        let nonce = random_hex(16);
+        let message = format!(
+            "{}\n\nWallet: {}\nNonce: {}\nIssued At: {}",
+            statement,
+            pubkey,
+            nonce,
+            unix_timestamp()
+        );
+
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|_, challenge| challenge.issued.elapsed() < CHALLENGE_TTL);
+        if pending.len() >= MAX_PENDING_CHALLENGES {
+            // Drop the oldest challenge rather than refusing new sign-ins
+            if let Some(oldest) = pending.iter().min_by_key(|(_, c)| c.issued).map(|(nonce, _)| nonce.clone()) {
+                pending.remove(&oldest);
+            }
+        }
+        pending.insert(nonce.clone(), Challenge {
+            pubkey: pubkey.to_string(),
+            statement: statement.to_string(),
+            message: message.clone(),
+            issued: Instant::now(),
+        });
+
+        (nonce, message)
+    }
+
+    // Function to consume a challenge, returning its message if it was issued to this wallet
+    // for this statement and is still valid. A mismatched attempt leaves the challenge in place, so
+    // someone who learns a nonce cannot burn it for the wallet it was issued to; expired ones are dropped.
+    pub fn take(&self, nonce: &str, pubkey: &str, statement: &str) -> Option<String> {
+        let mut pending = self.pending.lock().unwrap();
+        let challenge = pending.get(nonce)?;
+        if challenge.issued.elapsed() >= CHALLENGE_TTL {
+            pending.remove(nonce);
+            return None;
+        }
+        if challenge.pubkey != pubkey || challenge.statement != statement {
+            return None;
+        }
+        pending.remove(nonce).map(|challenge| challenge.message)
+    }
+}
+
+impl Default for SignInChallenges {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Function to generate `len` random bytes, hex-encoded
+pub fn random_hex(len: usize) -> String {
+    let mut bytes = vec![0u8; len];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+// Function to check an ed25519 wallet signature over a message
+pub fn verify_signature(pubkey: &str, signature: &str, message: &str) -> bool {
+    match (Pubkey::from_str(pubkey), Signature::from_str(signature)) {
+        (Ok(pubkey), Ok(signature)) => signature.verify(pubkey.as_ref(), message.as_bytes()),
+        _ => false,
+    }
+}
+
+// Function to get the bearer token of a request, if it sent one
+fn bearer_token(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get("Authorization")?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(|token| token.trim().to_string())
+}
+
+fn unauthorized(message: &str) -> actix_web::Error {
+    let response = HttpResponse::Unauthorized().json(json!({"error": message}));
+    InternalError::from_response(message.to_string(), response).into()
+}
+
+// The wallet a request is signed in as, taken from its `Authorization: Bearer <token>` header
+// or from the API key the rate limiting middleware authenticated.
+// Handlers that take this extractor reject unauthenticated requests with `401 Unauthorized`.
+#[derive(Debug, Clone)]
+pub struct AuthenticatedUser {
+    pub user_id: String,
+    pub role: Role,
+}
+
+impl AuthenticatedUser {
+    // Function to check that the user has at least `role`.
+    // Handlers turn the error into a `403 Forbidden` response with `.into()`.
+    pub fn require(&self, role: Role) -> Result<(), InsufficientRole> {
+        if self.role >= role {
+            Ok(())
+        } else {
+            Err(InsufficientRole { required: role, role: self.role })
+        }
+    }
+}
+
+// A user lacking the role a handler requires
+#[derive(Debug, Clone, Copy)]
+pub struct InsufficientRole {
+    required: Role,
+    role: Role,
+}
+
+impl From<InsufficientRole> for HttpResponse {
+    fn from(denied: InsufficientRole) -> Self {
+        HttpResponse::Forbidden().json(json!({
+            "error": "Insufficient role",
+            "required": denied.required,
+            "role": denied.role,
+        }))
+    }
+}
+
+fn internal_error(context: &str, e: impl std::fmt::Display) -> actix_web::Error {
+    error!("Error looking up {}: {}", context, e);
+    actix_web::error::ErrorInternalServerError(format!("Failed to look up {}", context))
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let key_owner = req.extensions().get::<RequestClient>().and_then(|client| client.user_id.clone());
+        let token = bearer_token(req);
+        let db = req.app_data::<web::Data<GraphDatabase>>().cloned();
+
+        Box::pin(async move {
+            let db = db.ok_or_else(|| unauthorized("Sessions are unavailable"))?;
+
+            let user_id = match key_owner {
+                Some(user_id) => user_id,
+                None => {
+                    let token = token.ok_or_else(|| unauthorized("Missing bearer token"))?;
+                    db.get_session_user(&token)
+                        .await
+                        .map_err(|e| internal_error("session", e))?
+                        .ok_or_else(|| unauthorized("Invalid or expired session"))?
+                }
+            };
+            let role = db.get_user_role(&user_id).await.map_err(|e| internal_error("user role", e))?;
+
+            Ok(AuthenticatedUser { user_id, role })
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NonceRequest {
+    pubkey: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyRequest {
+    pubkey: String,
+    nonce: String,
+    // Base58 ed25519 signature of the challenge message
+    signature: String,
+}
+
+// Issues a sign-in challenge: the wallet signs the returned message and sends it to `/auth/verify`
+pub async fn issue_nonce_endpoint(
+    challenges: web::Data<SignInChallenges>,
+    request: web::Json<NonceRequest>,
+) -> impl Responder {
+    let pubkey = request.into_inner().pubkey;
+    if Pubkey::from_str(&pubkey).is_err() {
+        return HttpResponse::BadRequest().json(json!({"error": "Invalid pubkey"}));
+    }
+
+    let (nonce, message) = challenges.issue(&pubkey, SIGN_IN_STATEMENT);
+    HttpResponse::Ok().json(json!({
+        "nonce": nonce,
+        "message": message,
+        "expires_in": CHALLENGE_TTL.as_secs(),
+    }))
+}
+
+// Verifies a signed challenge and starts a session for the wallet, which becomes its user id
+pub async fn verify_signature_endpoint(
+    db: web::Data<GraphDatabase>,
+    challenges: web::Data<SignInChallenges>,
+    request: web::Json<VerifyRequest>,
+) -> impl Responder {
+    let VerifyRequest { pubkey, nonce, signature } = request.into_inner();
+
+    let Some(message) = challenges.take(&nonce, &pubkey, SIGN_IN_STATEMENT) else {
+        return HttpResponse::Unauthorized().json(json!({"error": "Unknown or expired nonce"}));
+    };
+    if !verify_signature(&pubkey, &signature, &message) {
+        return HttpResponse::Unauthorized().json(json!({"error": "Signature does not match the challenge"}));
+    }
+
+    let token = random_hex(32);
+    if let Err(e) = db.ensure_user(&pubkey).await {
+        error!("Error creating user: {}", e);
+        return HttpResponse::InternalServerError().json(json!({"error": "Failed to start session"}));
+    }
+    if let Err(e) = db.create_session(&token, &pubkey, SESSION_TTL_SECS).await {
+        error!("Error creating session: {}", e);
+        return HttpResponse::InternalServerError().json(json!({"error": "Failed to start session"}));
+    }
+
+    HttpResponse::Ok().json(json!({
+        "token": token,
+        "user_id": pubkey,
+        "expires_in": SESSION_TTL_SECS,
+    }))
+}
+
+// Ends the session of the bearer token sent with the request
+pub async fn logout_endpoint(db: web::Data<GraphDatabase>, user: AuthenticatedUser, req: HttpRequest) -> impl Responder {
+    let Some(token) = bearer_token(&req) else {
+        return HttpResponse::Unauthorized().json(json!({"error": "Missing bearer token"}));
+    };
+
+    match db.delete_session(&token).await {
+        Ok(()) => HttpResponse::Ok().json(json!({"status": "Signed out", "user_id": user.user_id})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
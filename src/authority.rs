@@ -0,0 +1,174 @@
+use actix_web::{web, HttpResponse, Responder};
+use log::error;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::anchor::discriminator_for;
+use crate::auth::{verify_signature, AuthenticatedUser, SignInChallenges, CHALLENGE_TTL};
+use crate::graph_disc::{unix_timestamp, DiscriminatorName, GraphDatabase, ProgramClaim, Provenance, ProvenanceSource};
+use crate::listener_supervisor::ListenerSupervisor;
+use crate::query::{DiscriminatorSubmission, UploadDiscriminatorRequest};
+use crate::solana_connection::SolanaConnection;
+use crate::validation::{is_valid_pubkey, ProgramId};
+
+// Source recorded on names published by a program's upgrade authority
+const AUTHORITY_SOURCE: &str = "authority";
+
+// Function to build the statement an upgrade authority signs to claim a program
+fn claim_statement(program_id: &str) -> String {
+    format!("Claim program {} in the Discriminator Directory as its upgrade authority", program_id)
+}
+
+// Function to check a claimed authority against the one currently recorded on-chain
+async fn check_upgrade_authority(
+    solana_client: &SolanaConnection,
+    program_id: &str,
+    authority: &str,
+) -> Result<(), HttpResponse> {
+    match solana_client.get_upgrade_authority(program_id).await {
+        Ok(Some(current)) if current == authority => Ok(()),
+        Ok(Some(_)) => Err(HttpResponse::Forbidden().json(json!({"error": "Wallet is not the program's upgrade authority"}))),
+        Ok(None) => Err(HttpResponse::UnprocessableEntity().json(json!({
+            "error": "Program is immutable or not upgradeable, so it has no authority to claim it",
+        }))),
+        Err(e) => Err(HttpResponse::BadGateway().json(json!({"error": e}))),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ClaimChallengeRequest {
+    authority: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ClaimRequest {
+    authority: String,
+    nonce: String,
+    // Base58 ed25519 signature of the challenge message by the upgrade authority
+    signature: String,
+}
+
+// Issues a challenge for the upgrade authority of a program to sign
+pub async fn claim_challenge_endpoint(
+    solana_client: web::Data<SolanaConnection>,
+    challenges: web::Data<SignInChallenges>,
+    program_id: ProgramId,
+    request: web::Json<ClaimChallengeRequest>,
+) -> impl Responder {
+    let program_id = program_id.into_inner();
+    let authority = request.into_inner().authority;
+    if !is_valid_pubkey(&authority) {
+        return HttpResponse::BadRequest().json(json!({"error": "Invalid authority"}));
+    }
+
+    if let Err(response) = check_upgrade_authority(&solana_client.interactive(), &program_id, &authority).await {
+        return response;
+    }
+
+    let (nonce, message) = challenges.issue(&authority, &claim_statement(&program_id));
+    HttpResponse::Ok().json(json!({
+        "nonce": nonce,
+        "message": message,
+        "expires_in": CHALLENGE_TTL.as_secs(),
+    }))
+}
+
+// Verifies the authority's signature and lets the signed-in user publish official mappings for the program
+pub async fn claim_program_endpoint(
+    db: web::Data<GraphDatabase>,
+    solana_client: web::Data<SolanaConnection>,
+    challenges: web::Data<SignInChallenges>,
+    user: AuthenticatedUser,
+    program_id: ProgramId,
+    request: web::Json<ClaimRequest>,
+) -> impl Responder {
+    let program_id = program_id.into_inner();
+    let ClaimRequest { authority, nonce, signature } = request.into_inner();
+
+    let Some(message) = challenges.take(&nonce, &authority, &claim_statement(&program_id)) else {
+        return HttpResponse::Unauthorized().json(json!({"error": "Unknown or expired nonce"}));
+    };
+    if !verify_signature(&authority, &signature, &message) {
+        return HttpResponse::Unauthorized().json(json!({"error": "Signature does not match the challenge"}));
+    }
+    // The authority may have changed since the challenge was issued
+    if let Err(response) = check_upgrade_authority(&solana_client.interactive(), &program_id, &authority).await {
+        return response;
+    }
+
+    let claim = ProgramClaim {
+        _key: program_id.clone(),
+        program_id,
+        authority,
+        user_id: user.user_id,
+        claimed_at: unix_timestamp(),
+    };
+    match db.save_program_claim(&claim).await {
+        Ok(()) => HttpResponse::Ok().json(claim),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+// Shows who currently holds the claim on a program
+pub async fn get_program_claim_endpoint(db: web::Data<GraphDatabase>, program_id: ProgramId) -> impl Responder {
+    match db.get_program_claim(&program_id.into_inner()).await {
+        Ok(Some(claim)) => HttpResponse::Ok().json(claim),
+        Ok(None) => HttpResponse::NotFound().json(json!({"error": "Program has not been claimed"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+// Publishes an official mapping. Only the holder of the program's claim may do this, and only while
+// the claimed wallet is still the upgrade authority on-chain.
+pub async fn publish_official_endpoint(
+    db: web::Data<GraphDatabase>,
+    solana_client: web::Data<SolanaConnection>,
+    supervisor: web::Data<ListenerSupervisor>,
+    user: AuthenticatedUser,
+    program_id: ProgramId,
+    submission: web::Json<UploadDiscriminatorRequest>,
+) -> impl Responder {
+    let program_id = program_id.into_inner();
+
+    let claim = match db.get_program_claim(&program_id).await {
+        Ok(Some(claim)) if claim.user_id == user.user_id => claim,
+        Ok(_) => return HttpResponse::Forbidden().json(json!({"error": "You do not hold the claim on this program"})),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    };
+    if let Err(response) = check_upgrade_authority(&solana_client.interactive(), &program_id, &claim.authority).await {
+        return response;
+    }
+
+    let DiscriminatorSubmission { discriminator_data, kind, name, details } = match submission.into_inner().validate() {
+        Ok(submission) => submission,
+        Err(errors) => return HttpResponse::BadRequest().json(json!({"error": "Invalid submission", "fields": errors})),
+    };
+
+    // Recorded even though the entry may already exist, with the claimed upgrade authority as reference
+    let provenance = Provenance::new(ProvenanceSource::Authority, AUTHORITY_SOURCE, Some(claim.authority.clone()));
+
+    if let Err(e) = db.upload_discriminator(&program_id, kind, discriminator_data.clone(), Vec::new(), &user.user_id, provenance).await {
+        error!("Error uploading official discriminator: {}", e);
+        return HttpResponse::InternalServerError().json(json!({"error": "Failed to upload discriminator to DB"}));
+    }
+
+    let name = name.map(|name| DiscriminatorName {
+        verified: discriminator_for(kind, &name)[..] == discriminator_data[..],
+        name,
+        source: AUTHORITY_SOURCE.to_string(),
+    });
+    let verified = name.as_ref().map(|name| name.verified);
+
+    if let Err(e) = db.mark_official(&program_id, kind, &discriminator_data, &user.user_id, name, &details).await {
+        error!("Error marking discriminator official: {}", e);
+        return HttpResponse::InternalServerError().json(json!({"error": "Failed to upload discriminator to DB"}));
+    }
+
+    supervisor.start_if_unknown(&program_id);
+    HttpResponse::Ok().json(json!({
+        "status": "Official mapping published",
+        "discriminator": hex::encode(&discriminator_data),
+        "kind": kind,
+        "name_verified": verified,
+    }))
+}
@@ -0,0 +1,473 @@
+use actix_web::{web, HttpResponse, Responder};
+use serde::Serialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use thiserror::Error;
+
+use crate::auth::{random_hex, AuthenticatedUser};
+use crate::graph_disc::{
+    unix_timestamp, DatabaseError, DiscriminatorDetails, DiscriminatorKind, DiscriminatorName, GraphDatabase, JobSpec,
+    Provenance, ProvenanceSource, Role, Submission, SubmissionStatus, BYTECODE_CONTRIBUTOR,
+};
+use crate::jobs::{job_accepted, JobRunner};
+use crate::moderation::is_trusted;
+use crate::name_recovery::{NameCandidate, NameRecovery};
+use crate::validation::ProgramId;
+
+// sBPF opcodes the analyzer cares about
+const OP_LDDW: u8 = 0x18;
+const OP_JEQ_REG: u8 = 0x1d;
+const OP_JNE_REG: u8 = 0x5d;
+const OP_CALL: u8 = 0x85;
+
+// Instruction classes (low 3 bits of the opcode) that write their destination register
+const CLASS_LD: u8 = 0x00;
+const CLASS_LDX: u8 = 0x01;
+const CLASS_ALU: u8 = 0x04;
+const CLASS_ALU64: u8 = 0x07;
+
+const SHF_EXECINSTR: u64 = 0x4;
+
+#[derive(Error, Debug)]
+pub enum BytecodeError {
+    #[error("Not a 64-bit little-endian ELF file")]
+    NotElf,
+
+    #[error("ELF file is truncated or has out of range section headers")]
+    Malformed,
+
+    #[error("ELF file has no executable section")]
+    NoText,
+}
+
+// An 8 byte immediate the program compares against data, e.g. in its instruction dispatcher
+#[derive(Debug, Clone, Serialize)]
+pub struct BytecodeCandidate {
+    pub discriminator: String,
+    // Byte offset of the `lddw` that loads the constant, relative to the start of the text section
+    pub offset: usize,
+    #[serde(skip)]
+    pub bytes: [u8; 8],
+}
+
+// One decoded sBPF instruction slot
+struct Slot {
+    opcode: u8,
+    dst: usize,
+    src: usize,
+    imm: i32,
+}
+
+impl Slot {
+    fn read(bytes: &[u8]) -> Slot {
+        Slot {
+            opcode: bytes[0],
+            dst: (bytes[1] & 0x0f) as usize,
+            src: (bytes[1] >> 4) as usize,
+            imm: i32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+        }
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, BytecodeError> {
+    let bytes = offset.checked_add(2).and_then(|end| data.get(offset..end)).ok_or(BytecodeError::Malformed)?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, BytecodeError> {
+    let bytes = offset.checked_add(4).and_then(|end| data.get(offset..end)).ok_or(BytecodeError::Malformed)?;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Result<u64, BytecodeError> {
+    let bytes = offset.checked_add(8).and_then(|end| data.get(offset..end)).ok_or(BytecodeError::Malformed)?;
+    Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+// Function to get the contents of the section described by the header at `header`
+fn section_bytes(elf: &[u8], header: usize) -> Result<&[u8], BytecodeError> {
+    let offset = read_u64(elf, header.saturating_add(24))? as usize;
+    let size = read_u64(elf, header.saturating_add(32))? as usize;
+    let end = offset.checked_add(size).ok_or(BytecodeError::Malformed)?;
+    elf.get(offset..end).ok_or(BytecodeError::Malformed)
+}
+
+// Function to find the executable code of an sBPF ELF: the `.text` section, or else every executable section
+pub fn text_sections(elf: &[u8]) -> Result<Vec<&[u8]>, BytecodeError> {
+    // Magic, 64-bit class, little-endian data
+    if !elf.starts_with(b"\x7fELF") || elf.get(4) != Some(&2) || elf.get(5) != Some(&1) {
+        return Err(BytecodeError::NotElf);
+    }
+
+    let section_offset = read_u64(elf, 0x28)? as usize;
+    let section_size = read_u16(elf, 0x3a)? as usize;
+    let section_count = read_u16(elf, 0x3c)? as usize;
+    let names_index = read_u16(elf, 0x3e)? as usize;
+
+    let header = |index: usize| section_offset.checked_add(index.checked_mul(section_size)?);
+
+    let names = section_bytes(elf, header(names_index).ok_or(BytecodeError::Malformed)?).unwrap_or(&[]);
+
+    let mut executable = Vec::new();
+    for index in 0..section_count {
+        let header = header(index).ok_or(BytecodeError::Malformed)?;
+        let name_offset = read_u32(elf, header)? as usize;
+        let flags = read_u64(elf, header.saturating_add(8))?;
+
+        let name = names
+            .get(name_offset..)
+            .and_then(|rest| rest.split(|b| *b == 0).next())
+            .unwrap_or(&[]);
+
+        if name == b".text" {
+            return Ok(vec![section_bytes(elf, header)?]);
+        }
+        if flags & SHF_EXECINSTR != 0 {
+            executable.push(section_bytes(elf, header)?);
+        }
+    }
+
+    if executable.is_empty() {
+        Err(BytecodeError::NoText)
+    } else {
+        Ok(executable)
+    }
+}
+
+// Function to find 8 byte constants loaded with `lddw` and then compared register-to-register.
+// This is how compiled dispatchers compare the first 8 bytes of instruction or account data.
+pub fn find_candidates(text: &[u8]) -> Vec<BytecodeCandidate> {
+    let slots: Vec<&[u8]> = text.chunks_exact(8).collect();
+    // Register -> (constant, offset of the lddw that loaded it)
+    let mut constants: HashMap<usize, (u64, usize)> = HashMap::new();
+    let mut seen = HashSet::new();
+    let mut candidates = Vec::new();
+
+    let mut pc = 0;
+    while pc < slots.len() {
+        let slot = Slot::read(slots[pc]);
+
+        match slot.opcode {
+            OP_LDDW => {
+                let Some(high) = slots.get(pc + 1).map(|bytes| Slot::read(bytes)) else {
+                    break;
+                };
+                let value = (slot.imm as u32 as u64) | ((high.imm as u32 as u64) << 32);
+                constants.insert(slot.dst, (value, pc * 8));
+                pc += 2;
+                continue;
+            }
+            OP_JEQ_REG | OP_JNE_REG => {
+                for register in [slot.dst, slot.src] {
+                    let Some((value, offset)) = constants.get(&register) else {
+                        continue;
+                    };
+                    // Random 64-bit hashes have bits set in both halves; small constants are not discriminators
+                    if *value >> 32 == 0 || *value as u32 == 0 || *value == u64::MAX {
+                        continue;
+                    }
+                    let bytes = value.to_le_bytes();
+                    if seen.insert(bytes) {
+                        candidates.push(BytecodeCandidate {
+                            discriminator: hex::encode(bytes),
+                            offset: *offset,
+                            bytes,
+                        });
+                    }
+                }
+            }
+            OP_CALL => {
+                // Calls clobber the argument and return registers
+                for register in 0..=5 {
+                    constants.remove(&register);
+                }
+            }
+            opcode => {
+                if matches!(opcode & 0x07, CLASS_LD | CLASS_LDX | CLASS_ALU | CLASS_ALU64) {
+                    constants.remove(&slot.dst);
+                }
+            }
+        }
+
+        pc += 1;
+    }
+
+    candidates
+}
+
+// Function to analyze a program binary and return its candidate discriminators
+pub fn analyze_elf(elf: &[u8]) -> Result<Vec<BytecodeCandidate>, BytecodeError> {
+    let mut seen = HashSet::new();
+    let mut candidates = Vec::new();
+
+    for text in text_sections(elf)? {
+        for candidate in find_candidates(text) {
+            if seen.insert(candidate.bytes) {
+                candidates.push(candidate);
+            }
+        }
+    }
+
+    Ok(candidates)
+}
+
+// Function to pick the kind of a bytecode candidate from its dictionary matches, defaulting to instruction
+fn infer_kind(names: &[NameCandidate]) -> DiscriminatorKind {
+    let kinds: HashSet<DiscriminatorKind> = names.iter().map(|candidate| candidate.kind).collect();
+    match kinds.into_iter().collect::<Vec<_>>().as_slice() {
+        [kind] => *kind,
+        _ => DiscriminatorKind::Instruction,
+    }
+}
+
+// Function to get the name of a bytecode candidate: its only dictionary match of the inferred kind, if there is one
+fn matching_name(names: &[NameCandidate], kind: DiscriminatorKind) -> Option<&NameCandidate> {
+    let matching: Vec<&NameCandidate> = names.iter().filter(|name| name.kind == kind).collect();
+    match matching.as_slice() {
+        [name] => Some(name),
+        _ => None,
+    }
+}
+
+// Function to store a bytecode candidate, naming it when the dictionary has exactly one match of its kind.
+// `user_id` is recorded as the contributor. Returns the candidate with its inferred kind and dictionary matches.
+pub async fn store_candidate(
+    db: &GraphDatabase,
+    recovery: &NameRecovery,
+    program_id: &str,
+    candidate: &BytecodeCandidate,
+    binary_hash: &str,
+    user_id: &str,
+) -> Result<Value, DatabaseError> {
+    let names = recovery.candidates(&candidate.bytes);
+    let kind = infer_kind(&names);
+
+    db.upload_discriminator(
+        program_id,
+        kind,
+        candidate.bytes.to_vec(),
+        Vec::new(),
+        user_id,
+        Provenance::new(ProvenanceSource::Bytecode, BYTECODE_CONTRIBUTOR, Some(binary_hash.to_string())),
+    ).await?;
+
+    // A single dictionary match of the inferred kind is a verified name
+    if let Some(name) = matching_name(&names, kind) {
+        let name = DiscriminatorName {
+            name: name.name.clone(),
+            source: name.source.clone(),
+            verified: true,
+        };
+        let provenance = Provenance::new(ProvenanceSource::Dictionary, BYTECODE_CONTRIBUTOR, Some(binary_hash.to_string()));
+        db.name_discriminator(program_id, kind, &candidate.bytes, name, provenance, user_id).await?;
+    }
+
+    Ok(json!({
+        "discriminator": candidate.discriminator,
+        "offset": candidate.offset,
+        "kind": kind,
+        "names": names,
+    }))
+}
+
+// Function to queue a bytecode candidate from an uploaded binary for moderation.
+// Returns the candidate with its inferred kind, dictionary matches and submission id.
+async fn submit_candidate(
+    db: &GraphDatabase,
+    recovery: &NameRecovery,
+    program_id: &str,
+    candidate: &BytecodeCandidate,
+    binary_hash: &str,
+    user_id: &str,
+) -> Result<Value, DatabaseError> {
+    let names = recovery.candidates(&candidate.bytes);
+    let kind = infer_kind(&names);
+    let name = matching_name(&names, kind).map(|name| name.name.clone());
+
+    let submission = Submission {
+        _key: random_hex(8),
+        program_id: program_id.to_string(),
+        kind,
+        discriminator_data: candidate.bytes.to_vec(),
+        name_verified: name.as_ref().map(|_| true),
+        name,
+        details: DiscriminatorDetails {
+            source: Some(BYTECODE_CONTRIBUTOR.to_string()),
+            notes: Some(format!("Found at offset {} of uploaded binary {}", candidate.offset, binary_hash)),
+            ..DiscriminatorDetails::default()
+        },
+        user_id: user_id.to_string(),
+        status: SubmissionStatus::Pending,
+        created_at: unix_timestamp(),
+        reviewed_by: None,
+        reviewed_at: None,
+        review_note: None,
+    };
+    db.create_submission(&submission).await?;
+
+    Ok(json!({
+        "discriminator": candidate.discriminator,
+        "offset": candidate.offset,
+        "kind": kind,
+        "names": names,
+        "submission_id": submission._key,
+    }))
+}
+
+// Analyzes a program's bytecode and stores the candidates it finds.
+// A binary in the request body (a local `.so`) is analyzed right away. Anyone can craft a binary, so its
+// candidates are queued for moderation unless the uploader is trusted. When the body is empty the binary is
+// fetched from the program's ProgramData account by a background job, which the client polls.
+pub async fn analyze_program_endpoint(
+    db: web::Data<GraphDatabase>,
+    recovery: web::Data<NameRecovery>,
+    jobs: web::Data<JobRunner>,
+    user: AuthenticatedUser,
+    program_id: ProgramId,
+    body: web::Bytes,
+) -> impl Responder {
+    if let Err(denied) = user.require(Role::Contributor) {
+        return denied.into();
+    }
+    let program_id = program_id.into_inner();
+
+    if body.is_empty() {
+        return match jobs.enqueue(JobSpec::BytecodeAnalysis { program_id }, Some(user.user_id)).await {
+            Ok((job, added)) => job_accepted(&job, added),
+            Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+        };
+    }
+
+    let candidates = match analyze_elf(&body) {
+        Ok(candidates) => candidates,
+        Err(e) => return HttpResponse::UnprocessableEntity().json(json!({"error": e.to_string()})),
+    };
+    // Provenance references the exact binary the candidates were read from
+    let binary_hash = hex::encode(Sha256::digest(&body));
+
+    let trusted = match is_trusted(&db, &user).await {
+        Ok(trusted) => trusted,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    };
+
+    let mut results = Vec::new();
+    for candidate in &candidates {
+        let result = if trusted {
+            store_candidate(&db, &recovery, &program_id, candidate, &binary_hash, &user.user_id).await
+        } else {
+            submit_candidate(&db, &recovery, &program_id, candidate, &binary_hash, &user.user_id).await
+        };
+        match result {
+            Ok(result) => results.push(result),
+            Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+        }
+    }
+
+    if !trusted {
+        return HttpResponse::Accepted().json(json!({
+            "status": "Candidates queued for review",
+            "program_id": program_id,
+            "source": "upload",
+            "binary_hash": binary_hash,
+            "candidates": results,
+        }));
+    }
+
+    HttpResponse::Ok().json(json!({
+        "program_id": program_id,
+        "source": "upload",
+        "provenance": BYTECODE_CONTRIBUTOR,
+        "binary_hash": binary_hash,
+        "candidates": results,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DISCRIMINATOR: u64 = 0xf8c6_9e91_e175_87c8;
+
+    fn slot(opcode: u8, dst: u8, src: u8, imm: i32) -> [u8; 8] {
+        let imm = imm.to_le_bytes();
+        [opcode, dst | (src << 4), 0, 0, imm[0], imm[1], imm[2], imm[3]]
+    }
+
+    // `lddw` loads the constant into r1, then the dispatcher compares it with r2
+    fn dispatcher(constant: u64) -> Vec<u8> {
+        [
+            slot(OP_LDDW, 1, 0, constant as u32 as i32),
+            slot(0, 0, 0, (constant >> 32) as u32 as i32),
+            slot(OP_JEQ_REG, 2, 1, 0),
+            slot(0x95, 0, 0, 0),
+        ]
+        .concat()
+    }
+
+    // Function to wrap code in a minimal ELF with a null, a `.text` and a `.shstrtab` section
+    fn elf(text: &[u8]) -> Vec<u8> {
+        let names = b"\0.text\0.shstrtab\0";
+        let names_offset = 64;
+        let text_offset = names_offset + names.len();
+        let headers_offset = text_offset + text.len();
+
+        let mut elf = vec![0u8; 64];
+        elf[..6].copy_from_slice(b"\x7fELF\x02\x01");
+        elf[0x28..0x30].copy_from_slice(&(headers_offset as u64).to_le_bytes());
+        elf[0x3a..0x3c].copy_from_slice(&64u16.to_le_bytes());
+        elf[0x3c..0x3e].copy_from_slice(&3u16.to_le_bytes());
+        elf[0x3e..0x40].copy_from_slice(&2u16.to_le_bytes());
+        elf.extend_from_slice(names);
+        elf.extend_from_slice(text);
+
+        for (name, flags, offset, size) in [
+            (0u32, 0u64, 0usize, 0usize),
+            (1, SHF_EXECINSTR, text_offset, text.len()),
+            (7, 0, names_offset, names.len()),
+        ] {
+            let mut header = [0u8; 64];
+            header[..4].copy_from_slice(&name.to_le_bytes());
+            header[8..16].copy_from_slice(&flags.to_le_bytes());
+            header[24..32].copy_from_slice(&(offset as u64).to_le_bytes());
+            header[32..40].copy_from_slice(&(size as u64).to_le_bytes());
+            elf.extend_from_slice(&header);
+        }
+        elf
+    }
+
+    #[test]
+    fn finds_constants_compared_by_the_dispatcher() {
+        let candidates = find_candidates(&dispatcher(DISCRIMINATOR));
+
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].bytes, DISCRIMINATOR.to_le_bytes());
+        assert_eq!(candidates[0].offset, 0);
+    }
+
+    #[test]
+    fn ignores_small_constants_and_values_clobbered_by_calls() {
+        assert!(find_candidates(&dispatcher(1000)).is_empty());
+
+        let mut text = dispatcher(DISCRIMINATOR);
+        text.splice(16..16, slot(OP_CALL, 0, 0, 0));
+        assert!(find_candidates(&text).is_empty());
+    }
+
+    #[test]
+    fn scans_the_text_section_of_an_elf() {
+        let candidates = analyze_elf(&elf(&dispatcher(DISCRIMINATOR))).unwrap();
+
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].discriminator, hex::encode(DISCRIMINATOR.to_le_bytes()));
+    }
+
+    #[test]
+    fn rejects_files_that_are_not_elf() {
+        assert!(matches!(analyze_elf(b"not a program"), Err(BytecodeError::NotElf)));
+
+        let mut truncated = elf(&dispatcher(DISCRIMINATOR));
+        truncated.truncate(100);
+        assert!(matches!(analyze_elf(&truncated), Err(BytecodeError::Malformed)));
+    }
+}
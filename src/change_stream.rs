@@ -0,0 +1,385 @@
+use actix_web::http::header::CACHE_CONTROL;
+use actix_web::{web, Error, HttpRequest, HttpResponse, Responder};
+use actix_ws::Message;
+use futures::{stream, Stream, StreamExt};
+use log::error;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{HashSet, VecDeque};
+use std::time::{Duration, SystemTime};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Receiver;
+
+use crate::graph_disc::{
+    audit_key_prefix, AuditAction, AuditEvent, AuditFilter, DatabaseError, DiscriminatorKind, GraphDatabase,
+};
+use crate::validation::is_valid_pubkey;
+
+// Audit events read per query while catching up from the audit log
+const REPLAY_BATCH_SIZE: usize = 1000;
+// Audit keys are taken before a write commits, so events up to this old can still arrive live after a replay
+const REPLAY_OVERLAP: Duration = Duration::from_secs(60);
+// Comment lines sent on idle event streams so proxies keep the connection open
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+// Pings sent on idle WebSockets
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+const MAX_STREAM_PROGRAMS: usize = 100;
+const MAX_EVENT_ID_LENGTH: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeType {
+    // The discriminator was added to the directory
+    Created,
+    // The discriminator got its first name or a different one
+    Renamed,
+    // Anything else about the entry changed, e.g. its details, account count or provenance
+    Updated,
+}
+
+impl ChangeType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChangeType::Created => "created",
+            ChangeType::Renamed => "renamed",
+            ChangeType::Updated => "updated",
+        }
+    }
+}
+
+// A change to a directory entry, as pushed to stream subscribers
+#[derive(Debug, Clone, Serialize)]
+pub struct DiscriminatorChange {
+    // Key of the audit event that recorded the change; pass it back to resume after this change
+    pub id: String,
+    pub change: ChangeType,
+    pub action: AuditAction,
+    pub program_id: String,
+    pub kind: DiscriminatorKind,
+    pub discriminator: String,
+    pub name: Option<String>,
+    pub previous_name: Option<String>,
+    pub actor: String,
+    pub timestamp: u64,
+    // The entry as it is after the change
+    pub entry: Value,
+}
+
+impl DiscriminatorChange {
+    // Function to describe the change an audit event recorded, if it is a change to a directory entry
+    pub fn from_audit_event(event: AuditEvent) -> Option<Self> {
+        if event.collection != "Discriminators" {
+            return None;
+        }
+        let entry = event.after?;
+
+        // Entry keys start with the program id, which never contains an underscore
+        let program_id = event.document_key.split('_').next()?.to_string();
+        let kind = entry
+            .get("kind")
+            .and_then(|kind| serde_json::from_value(kind.clone()).ok())
+            .unwrap_or_default();
+        let discriminator = entry.get("discriminator_id").and_then(Value::as_str)?.to_string();
+
+        let name_of = |entry: &Value| entry.pointer("/name/name").and_then(Value::as_str).map(str::to_string);
+        let name = name_of(&entry);
+        let previous_name = event.before.as_ref().and_then(name_of);
+
+        let change = if event.before.is_none() {
+            ChangeType::Created
+        } else if name != previous_name {
+            ChangeType::Renamed
+        } else {
+            ChangeType::Updated
+        };
+
+        Some(DiscriminatorChange {
+            id: event._key,
+            change,
+            action: event.action,
+            program_id,
+            kind,
+            discriminator,
+            name,
+            previous_name,
+            actor: event.actor,
+            timestamp: event.timestamp,
+            entry,
+        })
+    }
+}
+
+// Filters of the change streams: `?program_id=<id>[,<id>...]&kind=instruction|account|event`.
+// `last_event_id` resumes after a change already received; the SSE `Last-Event-ID` header takes precedence.
+#[derive(Debug, Deserialize)]
+pub struct ChangeStreamQuery {
+    program_id: Option<String>,
+    kind: Option<DiscriminatorKind>,
+    last_event_id: Option<String>,
+}
+
+// Validated filters of a change stream
+#[derive(Debug, Clone)]
+pub struct ChangeFilter {
+    pub program_ids: HashSet<String>,
+    pub kind: Option<DiscriminatorKind>,
+}
+
+impl ChangeFilter {
+    pub fn matches(&self, change: &DiscriminatorChange) -> bool {
+        (self.program_ids.is_empty() || self.program_ids.contains(&change.program_id))
+            && (self.kind.is_none() || self.kind == Some(change.kind))
+    }
+}
+
+impl ChangeStreamQuery {
+    // Function to validate the query into its filters and resume point.
+    // On failure returns the message of the `400 Bad Request` response.
+    fn parse(self, last_event_id_header: Option<String>) -> Result<(ChangeFilter, Option<String>), String> {
+        let program_ids: HashSet<String> = self
+            .program_id
+            .iter()
+            .flat_map(|ids| ids.split(','))
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .map(str::to_string)
+            .collect();
+
+        if program_ids.len() > MAX_STREAM_PROGRAMS {
+            return Err(format!("At most {} program ids can be streamed at once", MAX_STREAM_PROGRAMS));
+        }
+        if let Some(invalid) = program_ids.iter().find(|id| !is_valid_pubkey(id)) {
+            return Err(format!("Invalid program id: {}", invalid));
+        }
+
+        let last_event_id = last_event_id_header.or(self.last_event_id).filter(|id| !id.is_empty());
+        if last_event_id.as_ref().is_some_and(|id| id.len() > MAX_EVENT_ID_LENGTH) {
+            return Err("Invalid event id".to_string());
+        }
+
+        Ok((ChangeFilter { program_ids, kind: self.kind }, last_event_id))
+    }
+}
+
+// State of one subscriber's change stream
+struct ChangeCursor {
+    db: web::Data<GraphDatabase>,
+    receiver: Receiver<AuditEvent>,
+    filter: ChangeFilter,
+    // Key of the newest audit event looked at; a replay continues after it
+    cursor: String,
+    // Set while catching up from the audit log, after a resume or when the live feed fell behind
+    replaying: bool,
+    // Keys of recent events sent during a replay, which can still arrive live and must not be sent twice
+    replayed: HashSet<String>,
+    // Replayed keys older than this can no longer arrive live
+    overlap_from: String,
+    pending: VecDeque<DiscriminatorChange>,
+}
+
+impl ChangeCursor {
+    fn start_replay(&mut self) {
+        self.replaying = true;
+        self.prune_replayed();
+    }
+
+    // Function to forget replayed keys too old to still arrive live
+    fn prune_replayed(&mut self) {
+        self.overlap_from = audit_key_prefix(SystemTime::now() - REPLAY_OVERLAP);
+        let overlap_from = &self.overlap_from;
+        self.replayed.retain(|key| key >= overlap_from);
+    }
+
+    // Function to read the next batch of the audit log after the cursor
+    async fn replay(&mut self) -> Result<(), DatabaseError> {
+        let filter = AuditFilter {
+            collection: Some("Discriminators".to_string()),
+            cursor: Some(self.cursor.clone()),
+            ..AuditFilter::default()
+        };
+        let events = self.db.list_audit_events(&filter, true, REPLAY_BATCH_SIZE).await?;
+        self.prune_replayed();
+        if events.len() < REPLAY_BATCH_SIZE {
+            self.replaying = false;
+        }
+
+        for event in events {
+            self.cursor = event._key.clone();
+            if event._key >= self.overlap_from {
+                self.replayed.insert(event._key.clone());
+            }
+            self.queue(event);
+        }
+        Ok(())
+    }
+
+    fn queue(&mut self, event: AuditEvent) {
+        if let Some(change) = DiscriminatorChange::from_audit_event(event) {
+            if self.filter.matches(&change) {
+                self.pending.push_back(change);
+            }
+        }
+    }
+
+    // Function to get the next change to send, waiting for one if needed.
+    // Returns `None` once the feed has closed.
+    async fn next(&mut self) -> Option<Result<DiscriminatorChange, DatabaseError>> {
+        loop {
+            if let Some(change) = self.pending.pop_front() {
+                return Some(Ok(change));
+            }
+
+            if self.replaying {
+                if let Err(e) = self.replay().await {
+                    return Some(Err(e));
+                }
+                continue;
+            }
+
+            match self.receiver.recv().await {
+                Ok(event) => {
+                    if !self.replayed.is_empty() {
+                        self.prune_replayed();
+                        if self.replayed.remove(&event._key) {
+                            continue;
+                        }
+                    }
+                    if event._key > self.cursor {
+                        self.cursor = event._key.clone();
+                    }
+                    self.queue(event);
+                }
+                // Events the receiver skipped are still in the audit log
+                Err(RecvError::Lagged(_)) => self.start_replay(),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+// Function to stream the changes matching a filter: first those recorded after `last_event_id`,
+// read back from the audit log, then new changes as they are written
+pub fn change_stream(
+    db: web::Data<GraphDatabase>,
+    filter: ChangeFilter,
+    last_event_id: Option<String>,
+) -> impl Stream<Item = Result<DiscriminatorChange, DatabaseError>> {
+    // Subscribed before replaying, so nothing written during the replay is missed
+    let receiver = db.subscribe_audit_events();
+
+    let mut cursor = ChangeCursor {
+        db,
+        receiver,
+        filter,
+        cursor: audit_key_prefix(SystemTime::now()),
+        replaying: false,
+        replayed: HashSet::new(),
+        overlap_from: String::new(),
+        pending: VecDeque::new(),
+    };
+    if let Some(last_event_id) = last_event_id {
+        cursor.cursor = last_event_id;
+        cursor.start_replay();
+    }
+
+    stream::unfold(cursor, |mut cursor| async move {
+        let change = cursor.next().await?;
+        Some((change, cursor))
+    })
+}
+
+// Streams changes to directory entries as Server-Sent Events. Each event's id can be sent back as
+// `Last-Event-ID` (browsers do so when they reconnect) to receive the changes missed in between.
+pub async fn discriminator_events_endpoint(
+    db: web::Data<GraphDatabase>,
+    query: web::Query<ChangeStreamQuery>,
+    req: HttpRequest,
+) -> impl Responder {
+    let last_event_id = req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let (filter, last_event_id) = match query.into_inner().parse(last_event_id) {
+        Ok(parsed) => parsed,
+        Err(e) => return HttpResponse::BadRequest().json(json!({"error": e})),
+    };
+
+    let events = change_stream(db, filter, last_event_id).map(|change| match change {
+        Ok(change) => {
+            let data = serde_json::to_string(&change).map_err(actix_web::error::ErrorInternalServerError)?;
+            Ok(web::Bytes::from(format!("id: {}\nevent: {}\ndata: {}\n\n", change.id, change.change.as_str(), data)))
+        }
+        Err(e) => {
+            // Ending the stream with an error closes the connection; the client resumes from its last event id
+            error!("Error streaming discriminator changes: {}", e);
+            Err(actix_web::error::ErrorInternalServerError("Failed to stream changes"))
+        }
+    });
+    let keepalive = stream::unfold((), |()| async {
+        tokio::time::sleep(KEEPALIVE_INTERVAL).await;
+        Some((Ok::<_, Error>(web::Bytes::from_static(b": keepalive\n\n")), ()))
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((CACHE_CONTROL, "no-cache"))
+        .streaming(stream::select(events, keepalive))
+}
+
+// Streams changes to directory entries over a WebSocket, one JSON text message per change.
+// Takes the same filters as the event stream, with `last_event_id` to resume.
+pub async fn discriminator_socket_endpoint(
+    db: web::Data<GraphDatabase>,
+    query: web::Query<ChangeStreamQuery>,
+    req: HttpRequest,
+    body: web::Payload,
+) -> Result<HttpResponse, Error> {
+    let (filter, last_event_id) = match query.into_inner().parse(None) {
+        Ok(parsed) => parsed,
+        Err(e) => return Ok(HttpResponse::BadRequest().json(json!({"error": e}))),
+    };
+    let (response, mut session, mut messages) = actix_ws::handle(&req, body)?;
+
+    actix_web::rt::spawn(async move {
+        let mut changes = Box::pin(change_stream(db, filter, last_event_id));
+        let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+
+        loop {
+            tokio::select! {
+                change = changes.next() => match change {
+                    Some(Ok(change)) => {
+                        if let Ok(text) = serde_json::to_string(&change) {
+                            if session.text(text).await.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                    Some(Err(e)) => {
+                        error!("Error streaming discriminator changes: {}", e);
+                        break;
+                    }
+                    None => break,
+                },
+                message = messages.next() => match message {
+                    Some(Ok(Message::Ping(bytes))) => {
+                        if session.pong(&bytes).await.is_err() {
+                            return;
+                        }
+                    }
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => {}
+                },
+                _ = heartbeat.tick() => {
+                    if session.ping(b"").await.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+
+        let _ = session.close(None).await;
+    });
+
+    Ok(response)
+}
@@ -0,0 +1,837 @@
+use actix_web::{web, HttpResponse, Responder};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{json, Map, Value};
+use solana_sdk::account::Account;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::VersionedTransaction;
+use std::collections::HashMap;
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::graph_disc::{DatabaseError, Discriminator, DiscriminatorKind, GraphDatabase};
+use crate::ingest::{executed_instructions, resolve_account_keys, split_discriminator, with_inner_instructions, ExecutedInstruction};
+use crate::solana_connection::SolanaConnection;
+use crate::validation::is_valid_pubkey;
+
+// Deepest nesting of layouts the decoder follows, and that `defined` types are expanded to
+pub const MAX_DECODE_DEPTH: usize = 32;
+// Most layout nodes expanding `defined` types may produce, so shared types cannot blow a layout up exponentially
+const MAX_RESOLVED_NODES: usize = 10_000;
+
+// Borsh layout of a value, in the same shape Anchor IDLs use ("u64", {"vec": "publicKey"}, {"array": ["u8", 32]}, ...)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum IdlType {
+    Bool,
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    U64,
+    I64,
+    U128,
+    I128,
+    F32,
+    F64,
+    String,
+    Bytes,
+    #[serde(alias = "pubkey")]
+    PublicKey,
+    Option(Box<IdlType>),
+    Vec(Box<IdlType>),
+    Array(Box<IdlType>, usize),
+    Struct(Vec<IdlField>),
+    Enum(Vec<IdlEnumVariant>),
+    // A type the IDL defines under `types`, `{"defined": "Pool"}` or `{"defined": {"name": "Pool"}}`
+    Defined(#[serde(deserialize_with = "defined_name")] String),
+}
+
+// Function to accept both the legacy and the Anchor 0.30 form of a `defined` reference
+fn defined_name<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum DefinedName {
+        Name(String),
+        Named { name: String },
+    }
+
+    match DefinedName::deserialize(deserializer)? {
+        DefinedName::Name(name) | DefinedName::Named { name } => Ok(name),
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdlField {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: IdlType,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdlEnumVariant {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fields: Option<IdlEnumFields>,
+}
+
+// Enum variants carry either named fields or a tuple of types
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum IdlEnumFields {
+    Named(Vec<IdlField>),
+    Tuple(Vec<IdlType>),
+}
+
+// A type an IDL defines under `types`, which `defined` references point to by name
+#[derive(Debug, Clone, Deserialize)]
+pub struct IdlTypeDefinition {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: IdlTypeDefinitionBody,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum IdlTypeDefinitionBody {
+    Struct {
+        #[serde(default)]
+        fields: Option<IdlEnumFields>,
+    },
+    Enum {
+        variants: Vec<IdlEnumVariant>,
+    },
+}
+
+impl IdlTypeDefinitionBody {
+    // Function to get the layout of the definition; tuple struct fields are named by position
+    fn layout(&self) -> IdlType {
+        match self {
+            IdlTypeDefinitionBody::Struct { fields: None } => IdlType::Struct(Vec::new()),
+            IdlTypeDefinitionBody::Struct { fields: Some(IdlEnumFields::Named(fields)) } => IdlType::Struct(fields.clone()),
+            IdlTypeDefinitionBody::Struct { fields: Some(IdlEnumFields::Tuple(types)) } => IdlType::Struct(
+                types
+                    .iter()
+                    .enumerate()
+                    .map(|(position, ty)| IdlField { name: position.to_string(), ty: ty.clone() })
+                    .collect(),
+            ),
+            IdlTypeDefinitionBody::Enum { variants } => IdlType::Enum(variants.clone()),
+        }
+    }
+}
+
+// Expands `defined` references against the type definitions of an IDL
+struct DefinedResolver<'a> {
+    types: HashMap<&'a str, IdlType>,
+    budget: usize,
+}
+
+impl DefinedResolver<'_> {
+    fn fields(&mut self, fields: &[IdlField], depth: usize) -> Vec<IdlField> {
+        fields
+            .iter()
+            .map(|field| IdlField { name: field.name.clone(), ty: self.resolve(&field.ty, depth) })
+            .collect()
+    }
+
+    fn resolve(&mut self, ty: &IdlType, depth: usize) -> IdlType {
+        if depth >= MAX_DECODE_DEPTH || self.budget == 0 {
+            return ty.clone();
+        }
+        self.budget -= 1;
+
+        match ty {
+            IdlType::Defined(name) => match self.types.get(name.as_str()).cloned() {
+                Some(layout) => self.resolve(&layout, depth + 1),
+                None => ty.clone(),
+            },
+            IdlType::Option(inner) => IdlType::Option(Box::new(self.resolve(inner, depth + 1))),
+            IdlType::Vec(inner) => IdlType::Vec(Box::new(self.resolve(inner, depth + 1))),
+            IdlType::Array(inner, len) => IdlType::Array(Box::new(self.resolve(inner, depth + 1)), *len),
+            IdlType::Struct(fields) => IdlType::Struct(self.fields(fields, depth + 1)),
+            IdlType::Enum(variants) => IdlType::Enum(
+                variants
+                    .iter()
+                    .map(|variant| IdlEnumVariant {
+                        name: variant.name.clone(),
+                        fields: variant.fields.as_ref().map(|fields| match fields {
+                            IdlEnumFields::Named(fields) => IdlEnumFields::Named(self.fields(fields, depth + 1)),
+                            IdlEnumFields::Tuple(types) => {
+                                IdlEnumFields::Tuple(types.iter().map(|ty| self.resolve(ty, depth + 1)).collect())
+                            }
+                        }),
+                    })
+                    .collect(),
+            ),
+            _ => ty.clone(),
+        }
+    }
+}
+
+// Function to replace the `defined` references of a field list with the layouts the IDL defines.
+// References to unknown types, or nested past the depth limit as recursive types are, stay in place
+// and fail when decoded.
+pub fn resolve_defined_types(fields: &[IdlField], types: &[IdlTypeDefinition]) -> Vec<IdlField> {
+    let mut resolver = DefinedResolver {
+        types: types.iter().map(|definition| (definition.name.as_str(), definition.ty.layout())).collect(),
+        budget: MAX_RESOLVED_NODES,
+    };
+    resolver.fields(fields, 0)
+}
+
+#[derive(Error, Debug)]
+pub enum DecodeError {
+    #[error("Data too short at byte {offset}: needed {needed} more bytes, {available} left")]
+    ShortData { offset: usize, needed: usize, available: usize },
+
+    #[error("{remaining} trailing bytes after decoding, starting at byte {offset}")]
+    TrailingData { offset: usize, remaining: usize },
+
+    #[error("Invalid bool value {value} at byte {offset}")]
+    InvalidBool { offset: usize, value: u8 },
+
+    #[error("Invalid option tag {value} at byte {offset}")]
+    InvalidOption { offset: usize, value: u8 },
+
+    #[error("Invalid enum variant {value} at byte {offset}")]
+    InvalidEnumVariant { offset: usize, value: u8 },
+
+    #[error("Invalid UTF-8 string at byte {offset}")]
+    InvalidUtf8 { offset: usize },
+
+    #[error("Layout type {name} is not defined, at byte {offset}")]
+    UndefinedType { offset: usize, name: String },
+
+    #[error("Layout nests deeper than {MAX_DECODE_DEPTH} levels at byte {offset}")]
+    TooDeep { offset: usize },
+}
+
+impl DecodeError {
+    pub fn offset(&self) -> usize {
+        match self {
+            DecodeError::ShortData { offset, .. }
+            | DecodeError::TrailingData { offset, .. }
+            | DecodeError::InvalidBool { offset, .. }
+            | DecodeError::InvalidOption { offset, .. }
+            | DecodeError::InvalidEnumVariant { offset, .. }
+            | DecodeError::InvalidUtf8 { offset }
+            | DecodeError::UndefinedType { offset, .. }
+            | DecodeError::TooDeep { offset } => *offset,
+        }
+    }
+}
+
+// Cursor over Borsh-encoded bytes
+pub struct BorshReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+    // Layouts currently being decoded, bounded so a deeply nested layout cannot exhaust the stack
+    depth: usize,
+}
+
+impl<'a> BorshReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        BorshReader { data, offset: 0, depth: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        let available = self.data.len() - self.offset;
+        if len > available {
+            return Err(DecodeError::ShortData { offset: self.offset, needed: len, available });
+        }
+        let bytes = &self.data[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn read_len(&mut self) -> Result<usize, DecodeError> {
+        Ok(u32::from_le_bytes(self.take_array()?) as usize)
+    }
+
+    // Function to decode a struct's fields into a JSON object
+    pub fn read_fields(&mut self, fields: &[IdlField]) -> Result<Value, DecodeError> {
+        let mut object = Map::new();
+        for field in fields {
+            object.insert(field.name.clone(), self.read(&field.ty)?);
+        }
+        Ok(Value::Object(object))
+    }
+
+    // Function to decode a single value of the given type into JSON.
+    // 64-bit integers stay exact JSON numbers; 128-bit integers are rendered as strings.
+    pub fn read(&mut self, ty: &IdlType) -> Result<Value, DecodeError> {
+        if self.depth >= MAX_DECODE_DEPTH {
+            return Err(DecodeError::TooDeep { offset: self.offset });
+        }
+        self.depth += 1;
+        let value = self.read_value(ty);
+        self.depth -= 1;
+        value
+    }
+
+    fn read_value(&mut self, ty: &IdlType) -> Result<Value, DecodeError> {
+        let offset = self.offset;
+
+        let value = match ty {
+            IdlType::Bool => match self.take_array::<1>()?[0] {
+                0 => json!(false),
+                1 => json!(true),
+                value => return Err(DecodeError::InvalidBool { offset, value }),
+            },
+            IdlType::U8 => json!(self.take_array::<1>()?[0]),
+            IdlType::I8 => json!(self.take_array::<1>()?[0] as i8),
+            IdlType::U16 => json!(u16::from_le_bytes(self.take_array()?)),
+            IdlType::I16 => json!(i16::from_le_bytes(self.take_array()?)),
+            IdlType::U32 => json!(u32::from_le_bytes(self.take_array()?)),
+            IdlType::I32 => json!(i32::from_le_bytes(self.take_array()?)),
+            IdlType::U64 => json!(u64::from_le_bytes(self.take_array()?)),
+            IdlType::I64 => json!(i64::from_le_bytes(self.take_array()?)),
+            IdlType::U128 => json!(u128::from_le_bytes(self.take_array()?).to_string()),
+            IdlType::I128 => json!(i128::from_le_bytes(self.take_array()?).to_string()),
+            IdlType::F32 => json!(f32::from_le_bytes(self.take_array()?)),
+            IdlType::F64 => json!(f64::from_le_bytes(self.take_array()?)),
+            IdlType::String => {
+                let len = self.read_len()?;
+                let bytes = self.take(len)?;
+                let string = std::str::from_utf8(bytes).map_err(|_| DecodeError::InvalidUtf8 { offset })?;
+                json!(string)
+            }
+            IdlType::Bytes => {
+                let len = self.read_len()?;
+                json!(BASE64.encode(self.take(len)?))
+            }
+            IdlType::PublicKey => json!(Pubkey::new_from_array(self.take_array()?).to_string()),
+            IdlType::Option(inner) => match self.take_array::<1>()?[0] {
+                0 => Value::Null,
+                1 => self.read(inner)?,
+                value => return Err(DecodeError::InvalidOption { offset, value }),
+            },
+            IdlType::Vec(inner) => {
+                let len = self.read_len()?;
+                // Every element takes at least one byte; reject lengths the data cannot hold before allocating
+                let available = self.data.len() - self.offset;
+                if len > available {
+                    return Err(DecodeError::ShortData { offset: self.offset, needed: len, available });
+                }
+                let mut items = Vec::with_capacity(len);
+                for _ in 0..len {
+                    items.push(self.read(inner)?);
+                }
+                Value::Array(items)
+            }
+            IdlType::Array(inner, len) => {
+                // Same bound as vectors, so a huge declared length cannot spin on empty data
+                let available = self.data.len() - self.offset;
+                if *len > available {
+                    return Err(DecodeError::ShortData { offset: self.offset, needed: *len, available });
+                }
+                let mut items = Vec::with_capacity((*len).min(self.data.len()));
+                for _ in 0..*len {
+                    items.push(self.read(inner)?);
+                }
+                Value::Array(items)
+            }
+            IdlType::Struct(fields) => self.read_fields(fields)?,
+            IdlType::Enum(variants) => {
+                let index = self.take_array::<1>()?[0];
+                let variant = variants
+                    .get(index as usize)
+                    .ok_or(DecodeError::InvalidEnumVariant { offset, value: index })?;
+                let fields = match &variant.fields {
+                    None => return Ok(json!(variant.name)),
+                    Some(IdlEnumFields::Named(fields)) => self.read_fields(fields)?,
+                    Some(IdlEnumFields::Tuple(types)) => {
+                        let mut items = Vec::with_capacity(types.len());
+                        for ty in types {
+                            items.push(self.read(ty)?);
+                        }
+                        Value::Array(items)
+                    }
+                };
+                let mut object = Map::new();
+                object.insert(variant.name.clone(), fields);
+                Value::Object(object)
+            }
+            IdlType::Defined(name) => return Err(DecodeError::UndefinedType { offset, name: name.clone() }),
+        };
+
+        Ok(value)
+    }
+
+    // Function to fail if any bytes were left undecoded
+    pub fn finish(&self) -> Result<(), DecodeError> {
+        let remaining = self.data.len() - self.offset;
+        if remaining > 0 {
+            return Err(DecodeError::TrailingData { offset: self.offset, remaining });
+        }
+        Ok(())
+    }
+}
+
+// Function to decode a complete argument list, rejecting both short and trailing data
+pub fn decode_args(fields: &[IdlField], data: &[u8]) -> Result<Value, DecodeError> {
+    let mut reader = BorshReader::new(data);
+    let value = reader.read_fields(fields)?;
+    reader.finish()?;
+    Ok(value)
+}
+
+// Text encodings accepted for raw bytes
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DataEncoding {
+    // Instruction data is shown as base58 by explorers and the JSON RPC
+    #[default]
+    Base58,
+    Base64,
+    Hex,
+}
+
+impl DataEncoding {
+    pub fn decode(&self, value: &str) -> Result<Vec<u8>, String> {
+        match self {
+            DataEncoding::Base58 => bs58::decode(value).into_vec().map_err(|e| e.to_string()),
+            DataEncoding::Base64 => BASE64.decode(value).map_err(|e| e.to_string()),
+            DataEncoding::Hex => hex::decode(value).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DecodeInstructionRequest {
+    program_id: String,
+    data: String,
+    #[serde(default)]
+    encoding: DataEncoding,
+}
+
+// Decodes raw instruction data using the directory: the discriminator gives the name, and the
+// stored IDL layout, when known, gives typed arguments
+pub async fn decode_instruction_endpoint(
+    db: web::Data<GraphDatabase>,
+    request: web::Json<DecodeInstructionRequest>,
+) -> impl Responder {
+    let DecodeInstructionRequest { program_id, data, encoding } = request.into_inner();
+
+    if !is_valid_pubkey(&program_id) {
+        return HttpResponse::BadRequest().json(json!({"error": "Invalid program_id"}));
+    }
+
+    let data = match encoding.decode(&data) {
+        Ok(data) => data,
+        Err(e) => return HttpResponse::BadRequest().json(json!({"error": format!("Invalid {:?} data: {}", encoding, e)})),
+    };
+    let Some((discriminator_data, args_data)) = split_discriminator(&data) else {
+        return HttpResponse::BadRequest().json(json!({"error": "Instruction data is shorter than an 8 byte discriminator"}));
+    };
+
+    let discriminator = match db.get_discriminator(&program_id, DiscriminatorKind::Instruction, &discriminator_data).await {
+        Ok(Some(discriminator)) => discriminator,
+        Ok(None) => {
+            return HttpResponse::NotFound().json(json!({
+                "error": "Unknown discriminator",
+                "discriminator": hex::encode(&discriminator_data),
+            }))
+        }
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    };
+
+    let args = match discriminator.args() {
+        Some(layout) => match decode_args(layout, &args_data) {
+            Ok(args) => Some(args),
+            Err(e) => {
+                return HttpResponse::UnprocessableEntity().json(json!({
+                    "error": e.to_string(),
+                    // Report the offset within the full instruction data, discriminator included
+                    "offset": e.offset() + 8,
+                }))
+            }
+        },
+        None => None,
+    };
+
+    HttpResponse::Ok().json(json!({
+        "program_id": program_id,
+        "discriminator": hex::encode(&discriminator_data),
+        "name": discriminator.name(),
+        "layout_known": args.is_some(),
+        "args": args,
+    }))
+}
+
+// Transactions are sent base64 encoded by wallets and `sendTransaction`
+fn default_transaction_encoding() -> DataEncoding {
+    DataEncoding::Base64
+}
+
+// Exactly one of `signature` or `transaction` (a serialized legacy or v0 transaction) is expected
+#[derive(Debug, Deserialize)]
+pub struct DecodeTransactionRequest {
+    signature: Option<String>,
+    transaction: Option<String>,
+    #[serde(default = "default_transaction_encoding")]
+    encoding: DataEncoding,
+}
+
+// Function to annotate an executed instruction with what the directory knows about it.
+// Directory lookups are cached per request, since transactions often repeat the same instruction.
+async fn annotate_instruction(
+    db: &GraphDatabase,
+    known: &mut HashMap<(String, Vec<u8>), Option<Discriminator>>,
+    instruction: &ExecutedInstruction,
+) -> Result<Value, DatabaseError> {
+    let mut annotated = json!({
+        "index": instruction.index,
+        "inner_index": instruction.inner_index,
+        "program_id": instruction.program_id,
+        "data": bs58::encode(&instruction.data).into_string(),
+    });
+
+    let Some((discriminator_data, args_data)) = split_discriminator(&instruction.data) else {
+        annotated["accounts"] = json!(instruction.accounts.iter().map(|pubkey| json!({"pubkey": pubkey})).collect::<Vec<_>>());
+        return Ok(annotated);
+    };
+
+    let cache_key = (instruction.program_id.clone(), discriminator_data.clone());
+    if !known.contains_key(&cache_key) {
+        let discriminator = db
+            .get_discriminator(&instruction.program_id, DiscriminatorKind::Instruction, &discriminator_data)
+            .await?;
+        known.insert(cache_key.clone(), discriminator);
+    }
+    let discriminator = known[&cache_key].as_ref();
+
+    let account_names = discriminator.and_then(Discriminator::accounts).unwrap_or_default();
+    let accounts: Vec<Value> = instruction
+        .accounts
+        .iter()
+        .enumerate()
+        .map(|(position, pubkey)| json!({"name": account_names.get(position), "pubkey": pubkey}))
+        .collect();
+
+    annotated["discriminator"] = json!(hex::encode(&discriminator_data));
+    annotated["known"] = json!(discriminator.is_some());
+    annotated["name"] = json!(discriminator.and_then(Discriminator::name));
+    annotated["accounts"] = json!(accounts);
+
+    if let Some(layout) = discriminator.and_then(Discriminator::args) {
+        match decode_args(layout, &args_data) {
+            Ok(args) => annotated["args"] = args,
+            Err(e) => {
+                annotated["decode_error"] = json!({"error": e.to_string(), "offset": e.offset() + 8});
+            }
+        }
+    }
+
+    Ok(annotated)
+}
+
+// Decodes every instruction of a transaction using the directory.
+// A signature is fetched from the chain and yields the inner instructions that executed. A raw transaction
+// has its lookup tables resolved on-chain and is simulated for its inner instructions; when the simulation
+// fails, only its top-level instructions are decoded and `inner_instructions` is "unavailable".
+pub async fn decode_transaction_endpoint(
+    db: web::Data<GraphDatabase>,
+    solana_client: web::Data<SolanaConnection>,
+    request: web::Json<DecodeTransactionRequest>,
+) -> impl Responder {
+    let DecodeTransactionRequest { signature, transaction, encoding } = request.into_inner();
+    let solana_client = solana_client.interactive();
+
+    let mut simulation_error = None;
+    let (instructions, inner_instructions) = match (signature, transaction) {
+        (Some(signature), None) => {
+            if Signature::from_str(&signature).is_err() {
+                return HttpResponse::BadRequest().json(json!({"error": "Invalid transaction signature"}));
+            }
+            let transaction = match solana_client.get_transaction(&signature).await {
+                Ok(transaction) => transaction,
+                Err(e) => return HttpResponse::BadGateway().json(json!({"error": e.to_string()})),
+            };
+            match executed_instructions(&transaction) {
+                Some(instructions) => (instructions, "executed"),
+                None => return HttpResponse::BadGateway().json(json!({"error": "RPC returned an undecodable transaction"})),
+            }
+        }
+        (None, Some(transaction)) => {
+            let bytes = match encoding.decode(&transaction) {
+                Ok(bytes) => bytes,
+                Err(e) => return HttpResponse::BadRequest().json(json!({"error": format!("Invalid {:?} data: {}", encoding, e)})),
+            };
+            let transaction: VersionedTransaction = match bincode::deserialize(&bytes) {
+                Ok(transaction) => transaction,
+                Err(e) => return HttpResponse::BadRequest().json(json!({"error": format!("Invalid transaction: {}", e)})),
+            };
+            let (writable, readonly) = match solana_client.resolve_lookup_tables(&transaction.message).await {
+                Ok(loaded) => loaded,
+                Err(e) => return HttpResponse::BadGateway().json(json!({"error": e})),
+            };
+            let account_keys = resolve_account_keys(&transaction.message, &writable, &readonly);
+
+            match solana_client.simulate_transaction(&transaction).await.map(|simulation| simulation.inner_instructions) {
+                Ok(Some(inner)) => (with_inner_instructions(&transaction.message, &account_keys, &inner), "simulated"),
+                Ok(None) => (with_inner_instructions(&transaction.message, &account_keys, &[]), "unavailable"),
+                Err(e) => {
+                    simulation_error = Some(e);
+                    (with_inner_instructions(&transaction.message, &account_keys, &[]), "unavailable")
+                }
+            }
+        }
+        _ => {
+            return HttpResponse::BadRequest().json(json!({"error": "Provide either a signature or a transaction"}))
+        }
+    };
+
+    let mut known = HashMap::new();
+    let mut annotated = Vec::with_capacity(instructions.len());
+    for instruction in &instructions {
+        match annotate_instruction(&db, &mut known, instruction).await {
+            Ok(instruction) => annotated.push(instruction),
+            Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+        }
+    }
+
+    let mut response = json!({
+        "inner_instructions": inner_instructions,
+        "instructions": annotated,
+    });
+    if let Some(e) = simulation_error {
+        response["simulation_error"] = json!(e);
+    }
+    HttpResponse::Ok().json(response)
+}
+
+// Most dashboards batch by page; getMultipleAccounts accepts at most 100 keys per call
+const MAX_BATCH_ACCOUNTS: usize = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct DecodeAccountsRequest {
+    addresses: Vec<String>,
+}
+
+// Function to decode an account's data using the layout stored for its owner and discriminator.
+// Accounts are often allocated larger than their contents, so trailing bytes are reported rather than rejected.
+async fn decode_account(
+    db: &GraphDatabase,
+    known: &mut HashMap<(String, Vec<u8>), Option<Discriminator>>,
+    address: &str,
+    account: &Account,
+) -> Result<Value, DatabaseError> {
+    let owner = account.owner.to_string();
+    let mut decoded = json!({
+        "address": address,
+        "owner": owner,
+        "lamports": account.lamports,
+        "data_len": account.data.len(),
+    });
+
+    let Some((discriminator_data, data)) = split_discriminator(&account.data) else {
+        decoded["known"] = json!(false);
+        return Ok(decoded);
+    };
+
+    let cache_key = (owner.clone(), discriminator_data.clone());
+    if !known.contains_key(&cache_key) {
+        let discriminator = db.get_discriminator(&owner, DiscriminatorKind::Account, &discriminator_data).await?;
+        known.insert(cache_key.clone(), discriminator);
+    }
+    let discriminator = known[&cache_key].as_ref();
+
+    decoded["discriminator"] = json!(hex::encode(&discriminator_data));
+    decoded["known"] = json!(discriminator.is_some());
+    decoded["name"] = json!(discriminator.and_then(Discriminator::name));
+    decoded["layout_known"] = json!(discriminator.and_then(Discriminator::args).is_some());
+
+    if let Some(layout) = discriminator.and_then(Discriminator::args) {
+        let mut reader = BorshReader::new(&data);
+        match reader.read_fields(layout) {
+            Ok(fields) => {
+                decoded["data"] = fields;
+                decoded["trailing_bytes"] = json!(data.len() - reader.offset);
+            }
+            Err(e) => {
+                decoded["decode_error"] = json!({"error": e.to_string(), "offset": e.offset() + 8});
+            }
+        }
+    }
+
+    Ok(decoded)
+}
+
+// Fetches an account and decodes its data using the directory entry of its owning program
+pub async fn decode_account_endpoint(
+    db: web::Data<GraphDatabase>,
+    solana_client: web::Data<SolanaConnection>,
+    address: web::Path<String>,
+) -> impl Responder {
+    let address = address.into_inner();
+    if !is_valid_pubkey(&address) {
+        return HttpResponse::BadRequest().json(json!({"error": "Invalid account address"}));
+    }
+
+    let account = match solana_client.interactive().get_multiple_accounts(std::slice::from_ref(&address)).await {
+        Ok(mut accounts) => match accounts.pop().flatten() {
+            Some(account) => account,
+            None => return HttpResponse::NotFound().json(json!({"error": "Account not found", "address": address})),
+        },
+        Err(e) => return HttpResponse::BadGateway().json(json!({"error": e})),
+    };
+
+    match decode_account(&db, &mut HashMap::new(), &address, &account).await {
+        Ok(decoded) => HttpResponse::Ok().json(decoded),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+// Batch variant of `decode_account_endpoint` for up to 100 addresses, fetched with a single getMultipleAccounts call.
+// Results keep the order of the request; missing accounts are reported as not found.
+pub async fn decode_accounts_endpoint(
+    db: web::Data<GraphDatabase>,
+    solana_client: web::Data<SolanaConnection>,
+    request: web::Json<DecodeAccountsRequest>,
+) -> impl Responder {
+    let addresses = request.into_inner().addresses;
+
+    if addresses.is_empty() || addresses.len() > MAX_BATCH_ACCOUNTS {
+        return HttpResponse::BadRequest().json(json!({
+            "error": format!("Between 1 and {} addresses are accepted", MAX_BATCH_ACCOUNTS),
+        }));
+    }
+    let invalid: Vec<&String> = addresses.iter().filter(|address| !is_valid_pubkey(address)).collect();
+    if !invalid.is_empty() {
+        return HttpResponse::BadRequest().json(json!({"error": "Invalid account addresses", "addresses": invalid}));
+    }
+
+    let accounts = match solana_client.interactive().get_multiple_accounts(&addresses).await {
+        Ok(accounts) => accounts,
+        Err(e) => return HttpResponse::BadGateway().json(json!({"error": e})),
+    };
+
+    let mut known = HashMap::new();
+    let mut results = Vec::with_capacity(addresses.len());
+    for (address, account) in addresses.iter().zip(accounts) {
+        let Some(account) = account else {
+            results.push(json!({"address": address, "error": "Account not found"}));
+            continue;
+        };
+        match decode_account(&db, &mut known, address, &account).await {
+            Ok(decoded) => results.push(decoded),
+            Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+        }
+    }
+
+    HttpResponse::Ok().json(json!({ "accounts": results }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(layout: Value) -> Vec<IdlField> {
+        serde_json::from_value(layout).unwrap()
+    }
+
+    #[test]
+    fn decodes_typed_arguments() {
+        let layout = fields(json!([
+            {"name": "amount", "type": "u64"},
+            {"name": "owner", "type": "publicKey"},
+            {"name": "memo", "type": {"option": "string"}},
+            {"name": "levels", "type": {"vec": "u16"}},
+            {"name": "side", "type": {"enum": [{"name": "Bid"}, {"name": "Ask", "fields": [{"name": "limit", "type": "i64"}]}]}},
+        ]));
+
+        let mut data = 42u64.to_le_bytes().to_vec();
+        data.extend_from_slice(&[7; 32]);
+        data.extend_from_slice(&[1, 2, 0, 0, 0, b'h', b'i']);
+        data.extend_from_slice(&[2, 0, 0, 0, 1, 0, 2, 0]);
+        data.push(1);
+        data.extend_from_slice(&(-5i64).to_le_bytes());
+
+        let decoded = decode_args(&layout, &data).unwrap();
+        assert_eq!(decoded["amount"], json!(42));
+        assert_eq!(decoded["owner"], json!(Pubkey::new_from_array([7; 32]).to_string()));
+        assert_eq!(decoded["memo"], json!("hi"));
+        assert_eq!(decoded["levels"], json!([1, 2]));
+        assert_eq!(decoded["side"], json!({"Ask": {"limit": -5}}));
+    }
+
+    #[test]
+    fn reports_short_and_trailing_data() {
+        let layout = fields(json!([{"name": "amount", "type": "u64"}]));
+
+        match decode_args(&layout, &[1, 2, 3]) {
+            Err(DecodeError::ShortData { offset: 0, needed: 8, available: 3 }) => {}
+            other => panic!("expected short data, got {:?}", other),
+        }
+        match decode_args(&layout, &[0; 10]) {
+            Err(DecodeError::TrailingData { offset: 8, remaining: 2 }) => {}
+            other => panic!("expected trailing data, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn resolves_defined_types_in_both_idl_forms() {
+        let types: Vec<IdlTypeDefinition> = serde_json::from_value(json!([
+            {"name": "Fee", "type": {"kind": "struct", "fields": [{"name": "bps", "type": "u16"}]}},
+            {"name": "Mode", "type": {"kind": "enum", "variants": [{"name": "Fast"}, {"name": "Slow"}]}},
+        ]))
+        .unwrap();
+        let layout = fields(json!([
+            {"name": "fee", "type": {"defined": "Fee"}},
+            {"name": "mode", "type": {"defined": {"name": "Mode", "generics": []}}},
+        ]));
+
+        let resolved = resolve_defined_types(&layout, &types);
+        let decoded = decode_args(&resolved, &[10, 0, 1]).unwrap();
+        assert_eq!(decoded, json!({"fee": {"bps": 10}, "mode": "Slow"}));
+    }
+
+    #[test]
+    fn undefined_types_fail_to_decode() {
+        let layout = resolve_defined_types(&fields(json!([{"name": "fee", "type": {"defined": "Fee"}}])), &[]);
+
+        match decode_args(&layout, &[0]) {
+            Err(DecodeError::UndefinedType { offset: 0, name }) => assert_eq!(name, "Fee"),
+            other => panic!("expected an undefined type, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn recursive_types_resolve_to_a_bounded_layout() {
+        let types: Vec<IdlTypeDefinition> = serde_json::from_value(json!([
+            {"name": "Node", "type": {"kind": "struct", "fields": [
+                {"name": "value", "type": "u8"},
+                {"name": "next", "type": {"option": {"defined": "Node"}}},
+            ]}},
+        ]))
+        .unwrap();
+        let layout = resolve_defined_types(&fields(json!([{"name": "head", "type": {"defined": "Node"}}])), &types);
+
+        let decoded = decode_args(&layout, &[1, 1, 2, 0]).unwrap();
+        assert_eq!(decoded, json!({"head": {"value": 1, "next": {"value": 2, "next": null}}}));
+    }
+
+    #[test]
+    fn rejects_layouts_nested_past_the_depth_limit() {
+        let mut ty = IdlType::U8;
+        for _ in 0..MAX_DECODE_DEPTH + 1 {
+            ty = IdlType::Option(Box::new(ty));
+        }
+        let layout = vec![IdlField { name: "deep".to_string(), ty }];
+
+        match decode_args(&layout, &[1; MAX_DECODE_DEPTH + 2]) {
+            Err(DecodeError::TooDeep { .. }) => {}
+            other => panic!("expected the depth limit, got {:?}", other),
+        }
+    }
+}
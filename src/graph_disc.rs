@@ -1,17 +1,23 @@
 use arangors::client::reqwest::ReqwestClient;
 use arangors::database::Database;
-use arangors::document::options::InsertOptions;
-use arangors::AqlQuery;
+use arangors::index::{Index, IndexSettings};
 use arangors::Connection;
 use arangors::ClientError;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
+use std::fmt;
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::join;
+use tokio::sync::broadcast;
 use thiserror::Error;
 use sha2::{Digest, Sha256};
 
+use crate::audit::current_request_id;
+use crate::decode::IdlField;
+use crate::rate_limit::RequestQuotas;
+
 // Structs for representing documents in the ArangoDB
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Program {
@@ -19,13 +25,293 @@ pub struct Program {
     id: String,
 }
 
+// Times `enqueue_job` tries to either insert a job or find the active one it collided with
+const ENQUEUE_ATTEMPTS: usize = 3;
+
+// Audit events kept for live subscribers that have not received them yet
+const AUDIT_BROADCAST_CAPACITY: usize = 1024;
+// Sample payloads kept per discriminator
+const SAMPLE_SLOTS: u64 = 16;
+
+// Contributor recorded for entries ingested automatically from the chain
+pub const ONCHAIN_CONTRIBUTOR: &str = "onchain";
+// Contributor recorded for candidates extracted from program bytecode
+pub const BYTECODE_CONTRIBUTOR: &str = "bytecode";
+
+// What the 8 byte prefix identifies: instruction data, account data or an emitted event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiscriminatorKind {
+    // Entries stored before kinds existed were all treated as instructions
+    #[default]
+    Instruction,
+    Account,
+    Event,
+}
+
+impl DiscriminatorKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DiscriminatorKind::Instruction => "instruction",
+            DiscriminatorKind::Account => "account",
+            DiscriminatorKind::Event => "event",
+        }
+    }
+}
+
+impl fmt::Display for DiscriminatorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Discriminator {
     _key: String,
     discriminator_id: String,
     discriminator_data: Vec<u8>,
+    #[serde(default)]
+    kind: DiscriminatorKind,
     instruction: Instruction,
     user_id: String,
+    // Number of on-chain accounts starting with this discriminator, when known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    account_count: Option<u64>,
+    // Data lengths of those accounts, when known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    account_spaces: Option<Vec<u64>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<DiscriminatorName>,
+    // Borsh layout of the data following the discriminator, e.g. from an IDL
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    args: Option<Vec<IdlField>>,
+    // Names of the accounts an instruction expects, in order, e.g. from an IDL
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    accounts: Option<Vec<String>>,
+    // Where the submitter took the mapping from, e.g. "idl" or "manual"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    source: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    notes: Option<String>,
+    // Published by the program's upgrade authority; outranks and cannot be overwritten by community submissions
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    official: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    official_by: Option<String>,
+    // Where the entry and its name were learned from, the first record of each source and component
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    provenance: Vec<Provenance>,
+    // Derived from the name and provenance whenever an entry is read; never stored
+    #[serde(default, skip_deserializing, skip_serializing_if = "Option::is_none")]
+    confidence: Option<Confidence>,
+}
+
+impl Discriminator {
+    pub fn name(&self) -> Option<&DiscriminatorName> {
+        self.name.as_ref()
+    }
+
+    pub fn args(&self) -> Option<&[IdlField]> {
+        self.args.as_deref()
+    }
+
+    pub fn accounts(&self) -> Option<&[String]> {
+        self.accounts.as_deref()
+    }
+
+    pub fn is_official(&self) -> bool {
+        self.official
+    }
+
+    pub fn confidence(&self) -> Confidence {
+        self.confidence.unwrap_or_else(|| self.derive_confidence())
+    }
+
+    // Function to work out how far the entry can be trusted:
+    // official mappings first, then names that hash to the discriminator or come from an on-chain IDL,
+    // then unverified names backed by independent evidence and entries observed on-chain, then everything else
+    fn derive_confidence(&self) -> Confidence {
+        if self.official {
+            return Confidence::Official;
+        }
+
+        // Entries stored before provenance was recorded only know who contributed them
+        let sources: HashSet<ProvenanceSource> = if self.provenance.is_empty() && self.user_id == ONCHAIN_CONTRIBUTOR {
+            HashSet::from([ProvenanceSource::Chain])
+        } else {
+            self.provenance.iter().map(|record| record.source).collect()
+        };
+
+        // Records pointing at the same transaction, account or file are one piece of evidence, e.g. a
+        // transaction seen on-chain and the name its logs gave
+        let evidence: HashSet<String> = self.provenance.iter().map(Provenance::evidence).collect();
+
+        match &self.name {
+            Some(name) if name.verified || sources.contains(&ProvenanceSource::OnchainIdl) => Confidence::High,
+            Some(_) if evidence.len() >= 2 => Confidence::Medium,
+            Some(name) if name.source == "logs" || sources.contains(&ProvenanceSource::Idl) => Confidence::Medium,
+            Some(_) => Confidence::Low,
+            None if sources.contains(&ProvenanceSource::Chain) => Confidence::Medium,
+            None => Confidence::Low,
+        }
+    }
+
+    // Function to fill in the derived confidence of an entry read from the database
+    fn with_confidence(mut self) -> Self {
+        self.confidence = Some(self.derive_confidence());
+        self
+    }
+}
+
+// How an entry or its name was learned
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProvenanceSource {
+    // Submitted by a contributor
+    Manual,
+    // Submitted by a contributor from an IDL file
+    Idl,
+    // Read from the IDL account Anchor publishes on-chain
+    OnchainIdl,
+    // Parsed from `Instruction:` lines in transaction logs
+    Logs,
+    // Recovered by hashing dictionary or directory names
+    Dictionary,
+    // Extracted from program bytecode
+    Bytecode,
+    // Observed in executed instructions, emitted events or program accounts
+    Chain,
+    // Published by the program's upgrade authority
+    Authority,
+}
+
+// A single record of where an entry came from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Provenance {
+    pub source: ProvenanceSource,
+    // e.g. the transaction signature, account address, IDL or binary hash, or submission id
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+    pub recorded_at: u64,
+    // Part of the service that stored it, e.g. "listener" or "name_recovery"
+    pub component: String,
+}
+
+impl Provenance {
+    pub fn new(source: ProvenanceSource, component: &str, reference: Option<String>) -> Self {
+        Provenance {
+            source,
+            reference,
+            recorded_at: unix_timestamp(),
+            component: component.to_string(),
+        }
+    }
+
+    // Function to identify what the record is evidence of: its reference, or else where it was recorded
+    fn evidence(&self) -> String {
+        match &self.reference {
+            Some(reference) => reference.clone(),
+            None => format!("{:?}/{}", self.source, self.component),
+        }
+    }
+}
+
+// How far a directory entry can be trusted, in increasing order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Confidence {
+    Low,
+    Medium,
+    High,
+    Official,
+}
+
+// What a write recorded in the audit log did
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Upload,
+    Name,
+    UpdateDetails,
+    AccountCount,
+    MarkOfficial,
+    CreateUser,
+    SetRole,
+    Submit,
+    Approve,
+    Reject,
+    Retract,
+    // A submission claimed for publishing went back to the queue because publishing failed
+    Release,
+    ClaimProgram,
+}
+
+// An immutable record of a change to a document, with the document as it was before and after
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEvent {
+    // Sortable by time: zero-padded microseconds followed by a random suffix and the document key
+    pub _key: String,
+    pub action: AuditAction,
+    pub collection: String,
+    pub document_key: String,
+    pub actor: String,
+    pub timestamp: u64,
+    // Id of the API request that made the change; `None` for background ingestion
+    pub request_id: Option<String>,
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+}
+
+// Filters of an audit log query. `cursor` is the key of the last event of the previous page.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuditFilter {
+    pub actor: Option<String>,
+    pub action: Option<AuditAction>,
+    pub collection: Option<String>,
+    pub document_key: Option<String>,
+    pub request_id: Option<String>,
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    pub cursor: Option<String>,
+}
+
+// Human readable name of a discriminator and where it was recovered from
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DiscriminatorName {
+    pub name: String,
+    // e.g. "logs" for names parsed from Anchor `Instruction:` log lines
+    pub source: String,
+    // Whether the name hashes to the discriminator under the Anchor derivation
+    pub verified: bool,
+}
+
+// Submitter supplied details of an entry; fields left as `None` keep their stored value
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DiscriminatorDetails {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub args: Option<Vec<IdlField>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub accounts: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+}
+
+// A directory entry that has bytes but no name yet
+#[derive(Debug, Deserialize)]
+pub struct UnnamedDiscriminator {
+    pub program_id: String,
+    #[serde(default)]
+    pub kind: DiscriminatorKind,
+    pub discriminator_data: Vec<u8>,
+}
+
+// A discriminator, an event one unless another kind was asked for, together with payloads it was seen with
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EventDiscriminator {
+    pub discriminator: Discriminator,
+    pub samples: Vec<Vec<u8>>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -39,23 +325,233 @@ pub struct Instruction {
 pub struct User {
     _key: String,
     id: String,
+    // Left out when unset so that upserting a user never resets an assigned role
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    role: Option<Role>,
+}
+
+// What a user may do, in increasing order of privilege
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Viewer,
+    // Every signed-in wallet starts as a contributor
+    #[default]
+    Contributor,
+    Moderator,
+    Admin,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SubmissionStatus {
+    Pending,
+    // Claimed by a moderator who is publishing it; approved once published
+    Publishing,
+    Approved,
+    Rejected,
+    // Withdrawn by the contributor
+    Retracted,
+}
+
+// A contributed mapping as it was submitted. Approved submissions are published to `Discriminators`;
+// pending and rejected ones only live here, so public queries never see them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Submission {
+    pub _key: String,
+    pub program_id: String,
+    pub kind: DiscriminatorKind,
+    pub discriminator_data: Vec<u8>,
+    pub name: Option<String>,
+    // Whether the name hashes to the discriminator under the Anchor derivation
+    #[serde(default)]
+    pub name_verified: Option<bool>,
+    #[serde(default)]
+    pub details: DiscriminatorDetails,
+    pub user_id: String,
+    pub status: SubmissionStatus,
+    pub created_at: u64,
+    #[serde(default)]
+    pub reviewed_by: Option<String>,
+    #[serde(default)]
+    pub reviewed_at: Option<u64>,
+    #[serde(default)]
+    pub review_note: Option<String>,
+}
+
+// A user's submission together with the names other users had approved for the same discriminator
+#[derive(Debug, Deserialize)]
+pub struct SubmissionWithCompetitors {
+    pub submission: Submission,
+    pub other_names: Vec<String>,
+}
+
+// Proof that a user controls a program: its upgrade authority signed a challenge
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProgramClaim {
+    pub _key: String,
+    pub program_id: String,
+    pub authority: String,
+    pub user_id: String,
+    pub claimed_at: u64,
+}
+
+// Work the job queue runs, with its parameters
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JobSpec {
+    // Stores the discriminator of every account a program owns
+    AccountScan { program_id: String },
+    // Ingests the program's transactions that landed after `until`, or all the RPC still returns
+    Backfill {
+        program_id: String,
+        #[serde(default)]
+        until: Option<String>,
+    },
+    // Extracts discriminator candidates from the deployed program binary
+    BytecodeAnalysis { program_id: String },
+    // Imports the IDL Anchor programs publish on-chain
+    IdlFetch { program_id: String },
+    // Writes the matching audit events to a file for download
+    AuditExport { filter: AuditFilter },
+}
+
+impl JobSpec {
+    pub fn kind(&self) -> JobKind {
+        match self {
+            JobSpec::AccountScan { .. } => JobKind::AccountScan,
+            JobSpec::Backfill { .. } => JobKind::Backfill,
+            JobSpec::BytecodeAnalysis { .. } => JobKind::BytecodeAnalysis,
+            JobSpec::IdlFetch { .. } => JobKind::IdlFetch,
+            JobSpec::AuditExport { .. } => JobKind::AuditExport,
+        }
+    }
+
+    pub fn program_id(&self) -> Option<&str> {
+        match self {
+            JobSpec::AccountScan { program_id }
+            | JobSpec::Backfill { program_id, .. }
+            | JobSpec::BytecodeAnalysis { program_id }
+            | JobSpec::IdlFetch { program_id } => Some(program_id),
+            JobSpec::AuditExport { .. } => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    AccountScan,
+    Backfill,
+    BytecodeAnalysis,
+    IdlFetch,
+    AuditExport,
+}
+
+impl JobKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobKind::AccountScan => "account_scan",
+            JobKind::Backfill => "backfill",
+            JobKind::BytecodeAnalysis => "bytecode_analysis",
+            JobKind::IdlFetch => "idl_fetch",
+            JobKind::AuditExport => "audit_export",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    // Waiting to run, either for the first time or for a retry after `run_after`
+    Queued,
+    Running,
+    Succeeded,
+    Cancelled,
+    // Failed on every attempt; kept as the dead-letter list until requeued
+    Dead,
+}
+
+// A job of the persistent queue
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    pub _key: String,
+    pub kind: JobKind,
+    pub spec: JobSpec,
+    // Jobs with the same key never run side by side, e.g. one account scan per program
+    pub dedup_key: Option<String>,
+    // The dedup key while the job is queued or running, cleared once it finishes.
+    // A unique index on it keeps a second active job with the same key from being inserted.
+    #[serde(default)]
+    pub active_key: Option<String>,
+    pub state: JobState,
+    pub attempts: u32,
+    pub max_attempts: u32,
+    // Earliest time the job may start; pushed back after every failed attempt
+    pub run_after: u64,
+    // What the job is currently doing, e.g. "fetching accounts"
+    pub stage: String,
+    pub processed: u64,
+    // Number of items to process, once known
+    pub total: Option<u64>,
+    pub result: Option<serde_json::Value>,
+    // Error of the most recent failed attempt
+    pub error: Option<String>,
+    pub requested_by: Option<String>,
+    // Request that enqueued the job; its writes are audited under this id
+    pub request_id: Option<String>,
+    pub created_at: u64,
+    pub started_at: Option<u64>,
+    pub finished_at: Option<u64>,
+}
+
+impl Job {
+    // Function to check whether the job finished without finding anything to store
+    pub fn found_nothing(&self) -> bool {
+        self.state == JobState::Succeeded && self.total == Some(0)
+    }
+}
+
+// A signed-in wallet's session. The key is the hash of the bearer token, so the token itself is never stored.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Session {
+    _key: String,
+    user_id: String,
+    created_at: u64,
+    expires_at: u64,
+}
+
+// An API key issued to a user. Only the hash of the key is stored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub _key: String,
+    pub key_hash: String,
+    pub user_id: String,
+    pub name: String,
+    pub quotas: RequestQuotas,
+    pub created_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revoked_at: Option<u64>,
 }
 
 // Structs for representing edges in the ArangoDB graph
 #[derive(Debug, Serialize, Deserialize)]
 pub struct HasDiscriminator {
+    _key: String,
     _from: String,
     _to: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct MappedTo {
+    _key: String,
     _from: String,
     _to: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ContributedBy {
+    _key: String,
     _from: String,
     _to: String,
 }
@@ -66,31 +562,37 @@ pub enum DatabaseError {
     #[error("ArangoDB client error: {0}")]
     ClientError(#[from] ClientError),
 
-    #[error("Failed to insert document into {collection}: {source}")]
-    DocumentInsertionError {
-        collection: String,
-        #[source]
-        source: ClientError,
-    },
-
     #[error("Failed to execute AQL query: {query} - {source}")]
     AqlQueryError {
         query: String,
         #[source]
         source: ClientError,
     },
+
+    #[error("Failed to serialize document: {0}")]
+    SerializationError(#[from] serde_json::Error),
+
+    #[error("{0} not found")]
+    NotFound(String),
+}
+
+// Function to get the time-sortable prefix audit event keys start with: zero-padded microseconds.
+// Every event recorded at or after `time` has a key that sorts after the prefix.
+pub fn audit_key_prefix(time: SystemTime) -> String {
+    let micros = time.duration_since(UNIX_EPOCH).map(|d| d.as_micros()).unwrap_or_default();
+    format!("{:017}", micros)
+}
+
+// Function to get the current time as seconds since the Unix epoch
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
 }
 
 // Struct for interacting with the ArangoDB graph database
 pub struct GraphDatabase {
     db: Arc<Database<ReqwestClient>>,
-    program_collection: Arc<arangors::collection::Collection<ReqwestClient>>,
-    discriminator_collection: Arc<arangors::collection::Collection<ReqwestClient>>,
-    instruction_collection: Arc<arangors::collection::Collection<ReqwestClient>>,
-    user_collection: Arc<arangors::collection::Collection<ReqwestClient>>,
-    has_discriminator_collection: Arc<arangors::collection::Collection<ReqwestClient>>,
-    mapped_to_collection: Arc<arangors::collection::Collection<ReqwestClient>>,
-    contributed_by_collection: Arc<arangors::collection::Collection<ReqwestClient>>,
+    // Every audit event written by this process, for live subscribers
+    audit_events: broadcast::Sender<AuditEvent>,
 }
 
 // Implement the Clone trait for GraphDatabase to allow cloning
@@ -98,13 +600,7 @@ impl Clone for GraphDatabase {
     fn clone(&self) -> Self {
         GraphDatabase {
             db: Arc::clone(&self.db),
-            program_collection: Arc::clone(&self.program_collection),
-            discriminator_collection: Arc::clone(&self.discriminator_collection),
-            instruction_collection: Arc::clone(&self.instruction_collection),
-            user_collection: Arc::clone(&self.user_collection),
-            has_discriminator_collection: Arc::clone(&self.has_discriminator_collection),
-            mapped_to_collection: Arc::clone(&self.mapped_to_collection),
-            contributed_by_collection: Arc::clone(&self.contributed_by_collection),
+            audit_events: self.audit_events.clone(),
         }
     }
 }
@@ -122,7 +618,13 @@ impl GraphDatabase {
             "Users",
             "HasDiscriminator",
             "MappedTo",
-            "ContributedBy"
+            "ContributedBy",
+            "Sessions",
+            "ApiKeys",
+            "Submissions",
+            "ProgramClaims",
+            "AuditLog",
+            "Jobs"
         ];
 
         for collection_name in collections {
@@ -136,26 +638,31 @@ impl GraphDatabase {
             }
         }
 
-        let program_collection = db.collection("Programs").await?;
-        let discriminator_collection = db.collection("Discriminators").await?;
-        let instruction_collection = db.collection("Instructions").await?;
-        let user_collection = db.collection("Users").await?;
-        let has_discriminator_collection = db.collection("HasDiscriminator").await?;
-        let mapped_to_collection = db.collection("MappedTo").await?;
-        let contributed_by_collection = db.collection("ContributedBy").await?;
+
+        Self::ensure_indexes(&db).await?;
 
         Ok(GraphDatabase {
             db: Arc::new(db),
-            program_collection: Arc::new(program_collection),
-            discriminator_collection: Arc::new(discriminator_collection),
-            instruction_collection: Arc::new(instruction_collection),
-            user_collection: Arc::new(user_collection),
-            has_discriminator_collection: Arc::new(has_discriminator_collection),
-            mapped_to_collection: Arc::new(mapped_to_collection),
-            contributed_by_collection: Arc::new(contributed_by_collection),
+            audit_events: broadcast::channel(AUDIT_BROADCAST_CAPACITY).0,
         })
     }
 
+    // Function to create the indexes the queries rely on for correctness. Creating an existing index is a no-op.
+    async fn ensure_indexes(db: &Database<ReqwestClient>) -> Result<(), ClientError> {
+        // Sparse, so finished jobs and jobs without a dedup key are not indexed
+        let active_jobs = Index::builder()
+            .name("active_job_dedup")
+            .fields(vec!["active_key".to_string()])
+            .settings(IndexSettings::Persistent {
+                unique: true,
+                sparse: true,
+                deduplicate: false,
+            })
+            .build();
+        db.create_index("Jobs", &active_jobs).await?;
+        Ok(())
+    }
+
     // Function to hash keys
     fn hash_key(input: &str) -> String {
         let mut hasher = Sha256::new();
@@ -163,13 +670,159 @@ impl GraphDatabase {
         hex::encode(hasher.finalize())
     }
 
+    // Function to pick the sample slot a payload is stored in, the same one for identical payloads
+    fn sample_slot(instruction_id: &str) -> u64 {
+        let digest = Sha256::digest(instruction_id.as_bytes());
+        u64::from_le_bytes(digest[..8].try_into().unwrap()) % SAMPLE_SLOTS
+    }
+
+    // Function to build the document key of a discriminator.
+    // Instruction keys keep their original `{program}_{discriminator}` form so existing entries stay addressable.
+    pub fn discriminator_key(program_id: &str, kind: DiscriminatorKind, discriminator_id: &str) -> String {
+        match kind {
+            DiscriminatorKind::Instruction => format!("{}_{}", program_id, discriminator_id),
+            _ => format!("{}_{}_{}", program_id, kind, discriminator_id),
+        }
+    }
+
+    // Function to insert a document, or merge its fields into the existing one.
+    // Fields the document leaves out are kept as they are.
+    async fn upsert_document<T: Serialize>(&self, collection: &str, document: &T) -> Result<(), DatabaseError> {
+        let aql = "
+        UPSERT { _key: @doc._key } INSERT @doc UPDATE @doc IN @@collection
+        ";
+
+        let mut bind_vars = HashMap::new();
+        bind_vars.insert("doc", serde_json::to_value(document)?);
+        bind_vars.insert("@collection", collection.into());
+
+        let _: Vec<serde_json::Value> = self.db.aql_bind_vars(aql, bind_vars).await
+            .map_err(|e| DatabaseError::AqlQueryError { query: aql.to_string(), source: e })?;
+        Ok(())
+    }
+
+    // Function to run a write whose query returns `{ before: OLD, after: NEW }` for every document it writes.
+    // Each document the write actually changed is recorded in `AuditLog` by the same query, so a change is never
+    // stored without its audit event. Returns the written documents as they are after the write.
+    // Sessions and API keys are credentials rather than directory data and are not written through here,
+    // and neither are the sample instructions, which change with every ingested transaction.
+    async fn audited_write(
+        &self,
+        write: &str,
+        mut bind_vars: HashMap<&str, serde_json::Value>,
+        collection: &str,
+        action: AuditAction,
+        actor: &str,
+    ) -> Result<Vec<serde_json::Value>, DatabaseError> {
+        // The sample instruction stored with a discriminator changes with every ingested transaction,
+        // so it is left out when deciding whether anything changed
+        let aql = format!("
+        LET changes = ({})
+        LET audited = (
+            FOR change IN changes
+                FILTER change.before == null
+                    OR UNSET(change.before, '_rev', 'instruction') != UNSET(change.after, '_rev', 'instruction')
+                INSERT MERGE(@audit, {{
+                    _key: CONCAT(@audit._key, '-', change.after._key),
+                    document_key: change.after._key,
+                    before: change.before,
+                    after: change.after
+                }}) INTO AuditLog
+                RETURN NEW
+        )
+        RETURN {{ changes: changes[*].after, audited: audited }}
+        ", write);
+
+        bind_vars.insert("audit", serde_json::json!({
+            "_key": format!("{}{:08x}", audit_key_prefix(SystemTime::now()), rand::random::<u32>()),
+            "action": action,
+            "collection": collection,
+            "actor": actor,
+            "timestamp": unix_timestamp(),
+            "request_id": current_request_id(),
+        }));
+
+        #[derive(Deserialize)]
+        struct AuditedWrite {
+            changes: Vec<serde_json::Value>,
+            audited: Vec<AuditEvent>,
+        }
+
+        let results: Vec<AuditedWrite> = self.db.aql_bind_vars(&aql, bind_vars).await
+            .map_err(|e| DatabaseError::AqlQueryError { query: aql.clone(), source: e })?;
+        let Some(outcome) = results.into_iter().next() else {
+            return Ok(Vec::new());
+        };
+
+        for event in outcome.audited {
+            // Sending only fails when nobody is subscribed
+            let _ = self.audit_events.send(event);
+        }
+        Ok(outcome.changes)
+    }
+
+    // Function to receive the audit events written from now on. A receiver that falls more than
+    // `AUDIT_BROADCAST_CAPACITY` events behind skips ahead and can catch up from the audit log.
+    pub fn subscribe_audit_events(&self) -> broadcast::Receiver<AuditEvent> {
+        self.audit_events.subscribe()
+    }
+
+    // Function to insert or merge a document like `upsert_document`, recording the change in the audit log
+    async fn audited_upsert<T: Serialize>(
+        &self,
+        collection: &str,
+        document: &T,
+        action: AuditAction,
+        actor: &str,
+    ) -> Result<(), DatabaseError> {
+        let write = "
+        UPSERT { _key: @doc._key } INSERT @doc UPDATE @doc IN @@collection
+            RETURN { before: OLD, after: NEW }
+        ";
+
+        let mut bind_vars = HashMap::new();
+        bind_vars.insert("doc", serde_json::to_value(document)?);
+        bind_vars.insert("@collection", collection.into());
+
+        self.audited_write(write, bind_vars, collection, action, actor).await?;
+        Ok(())
+    }
+
+    // Function to insert a discriminator, or merge it into the existing one unless that is an official mapping.
+    // The provenance record is added either way, unless one of the same source and component is already there.
+    async fn upsert_discriminator(&self, discriminator: &Discriminator, provenance: &Provenance) -> Result<(), DatabaseError> {
+        let write = "
+        UPSERT { _key: @doc._key }
+        INSERT MERGE(@doc, { provenance: [@provenance] })
+        UPDATE MERGE(
+            OLD.official == true ? {} : @doc,
+            { provenance: APPEND(OLD.provenance || [], (
+                FOR p IN OLD.provenance || []
+                    FILTER p.source == @provenance.source AND p.component == @provenance.component
+                    RETURN 1
+            ) == [] ? [@provenance] : []) }
+        )
+        IN Discriminators
+            RETURN { before: OLD, after: NEW }
+        ";
+
+        let mut bind_vars = HashMap::new();
+        bind_vars.insert("doc", serde_json::to_value(discriminator)?);
+        bind_vars.insert("provenance", serde_json::to_value(provenance)?);
+
+        self.audited_write(write, bind_vars, "Discriminators", AuditAction::Upload, &discriminator.user_id).await?;
+        Ok(())
+    }
+
     // Function to upload a discriminator to the database
     pub async fn upload_discriminator(
         &self,
         program_id: &str,
+        kind: DiscriminatorKind,
         discriminator_data: Vec<u8>,
         instruction_data: Vec<u8>,
         user_id: &str,
+        provenance: Provenance,
     ) -> Result<(), DatabaseError> {
 
         let discriminator_id = hex::encode(discriminator_data.clone());
@@ -180,8 +833,10 @@ impl GraphDatabase {
             id: program_id.to_string(),
         };
 
-        let discriminator_key = format!("{}_{}", program_id, discriminator_id);
-        let instruction_key = format!("{}_{}", program_id, Self::hash_key(&instruction_id));
+        let discriminator_key = Self::discriminator_key(program_id, kind, &discriminator_id);
+        // Samples go into a fixed number of slots per discriminator, so a busy program overwrites old
+        // samples instead of adding a document and an edge for every transaction
+        let instruction_key = format!("{}_{}", discriminator_key, Self::sample_slot(&instruction_id));
 
         // Debug logs to print the keys
         println!("Program key: {}", program._key);
@@ -192,12 +847,24 @@ impl GraphDatabase {
             _key: discriminator_key.clone(),
             discriminator_id: discriminator_id.clone(),
             discriminator_data: discriminator_data.clone(),
+            kind,
             instruction: Instruction {
                 _key: instruction_key.clone(),
                 instruction_id: instruction_id.clone(),
                 instruction_data: instruction_data.clone(),
             },
             user_id: user_id.to_string(),
+            account_count: None,
+            account_spaces: None,
+            name: None,
+            args: None,
+            accounts: None,
+            source: None,
+            notes: None,
+            official: false,
+            official_by: None,
+            provenance: Vec::new(),
+            confidence: None,
         };
 
         let instruction_doc = Instruction {
@@ -209,109 +876,257 @@ impl GraphDatabase {
         let user = User {
             _key: user_id.to_string(),
             id: user_id.to_string(),
+            role: None,
         };
 
-        // Concurrently insert the documents into the collections
+        // Concurrently upsert the documents, each change recorded in the audit log under the uploading user
         let (res1, res2, res3, res4) = join!(
-            self.program_collection.create_document(program, InsertOptions::builder().overwrite(true).build()),
-            self.discriminator_collection.create_document(discriminator_doc, InsertOptions::builder().overwrite(true).build()),
-            self.instruction_collection.create_document(instruction_doc, InsertOptions::builder().overwrite(true).build()),
-            self.user_collection.create_document(user, InsertOptions::builder().overwrite(true).build())
+            self.audited_upsert("Programs", &program, AuditAction::Upload, user_id),
+            // Upserted so that names and counts recorded earlier survive re-ingestion
+            self.upsert_discriminator(&discriminator_doc, &provenance),
+            // Samples are overwritten by every transaction, so they are not audited
+            self.upsert_document("Instructions", &instruction_doc),
+            // Upserted so that profile fields of the user are kept
+            self.audited_upsert("Users", &user, AuditAction::Upload, user_id)
         );
 
         // Check for errors
-        res1.map_err(|e| DatabaseError::DocumentInsertionError {
-            collection: "Programs".to_string(),
-            source: e,
-        })?;
-        res2.map_err(|e| DatabaseError::DocumentInsertionError {
-            collection: "Discriminators".to_string(),
-            source: e,
-        })?;
-        res3.map_err(|e| DatabaseError::DocumentInsertionError {
-            collection: "Instructions".to_string(),
-            source: e,
-        })?;
-        res4.map_err(|e| DatabaseError::DocumentInsertionError {
-            collection: "Users".to_string(),
-            source: e,
-        })?;
-
-
-        // Create edges for the graph
+        res1?;
+        res2?;
+        res3?;
+        res4?;
+
+
+        // Create edges for the graph, keyed so that uploading the same discriminator again does not add edges
         let edge_has_discriminator = HasDiscriminator {
+            _key: discriminator_key.clone(),
             _from: format!("Programs/{}", program_id),
             _to: format!("Discriminators/{}", discriminator_key.clone()),
         };
         let edge_mapped_to = MappedTo {
+            _key: instruction_key.clone(),
             _from: format!("Discriminators/{}", discriminator_key),
             _to: format!("Instructions/{}", instruction_key.clone()),
         };
         let edge_contributed_by = ContributedBy {
+            _key: format!("{}_{}", discriminator_key, user_id),
             _from: format!("Discriminators/{}", discriminator_key),
             _to: format!("Users/{}", user_id),
         };
 
 
-        // Insert edges concurrently
+        // Upsert edges concurrently
         let (edge_res1, edge_res2, edge_res3) = join!(
-            self.has_discriminator_collection.create_document(edge_has_discriminator, InsertOptions::builder().overwrite(true).build()),
-            self.mapped_to_collection.create_document(edge_mapped_to, InsertOptions::builder().overwrite(true).build()),
-            self.contributed_by_collection.create_document(edge_contributed_by, InsertOptions::builder().overwrite(true).build())
+            self.audited_upsert("HasDiscriminator", &edge_has_discriminator, AuditAction::Upload, user_id),
+            self.audited_upsert("MappedTo", &edge_mapped_to, AuditAction::Upload, user_id),
+            self.audited_upsert("ContributedBy", &edge_contributed_by, AuditAction::Upload, user_id)
         );
 
         // Check for errors
-        edge_res1.map_err(|e| DatabaseError::DocumentInsertionError {
-            collection: "HasDiscriminator".to_string(),
-            source: e,
-        })?;
-        edge_res2.map_err(|e| DatabaseError::DocumentInsertionError {
-            collection: "MappedTo".to_string(),
-            source: e,
-        })?;
-        edge_res3.map_err(|e| DatabaseError::DocumentInsertionError {
-            collection: "ContributedBy".to_string(),
-            source: e,
-        })?;
+        edge_res1?;
+        edge_res2?;
+        edge_res3?;
 
         Ok(())
     }
 
-    // Function to query discriminators and their instructions by program ID
-    pub async fn query_discriminators_and_instructions(&self, program_id: &str) -> Result<Vec<Discriminator>, DatabaseError> {
+    // Function to query discriminators and their instructions by program ID, optionally of a single kind
+    pub async fn query_discriminators_and_instructions(
+        &self,
+        program_id: &str,
+        kind: Option<DiscriminatorKind>,
+    ) -> Result<Vec<Discriminator>, DatabaseError> {
         let aql = "
         FOR d IN Discriminators
             FILTER d._key LIKE @program_id
+            FILTER @kind == null OR d.kind == @kind OR (@kind == 'instruction' AND d.kind == null)
+            SORT d.official == true DESC
             RETURN {discriminator: d}
         ";
 
         let mut bind_vars = HashMap::new();
         bind_vars.insert("program_id", format!("{}%", program_id).into());
+        bind_vars.insert("kind", kind.map(|kind| kind.as_str()).into());
 
         let results: Vec<HashMap<String, Discriminator>> = self.db.aql_bind_vars(aql, bind_vars).await
             .map_err(|e| DatabaseError::AqlQueryError { query: aql.to_string(), source: e })?;
 
         let mut discriminators = Vec::new();
         for result in results {
-            let discriminator: &Discriminator = match result.get("discriminator") {
-                Some(discriminator) => &discriminator.clone(),
-                None => {
-                    panic!("Discriminator not found");
-                }
+            let Some(discriminator) = result.get("discriminator") else {
+                return Err(DatabaseError::NotFound("Discriminator".to_string()));
             };
 
             discriminators.push(Discriminator {
-                _key: discriminator._key.clone(),
-                discriminator_id: discriminator.discriminator_id.clone(),
-                instruction: discriminator.instruction.clone(),
-                discriminator_data: discriminator.discriminator_data.clone(),
-                user_id: discriminator.user_id.clone(),
-            });
+                confidence: None,
+                ..discriminator.clone()
+            }.with_confidence());
         }
 
         Ok(discriminators)
     }
 
+    // Function to record how many on-chain accounts start with a discriminator, and their data lengths
+    pub async fn record_account_count(
+        &self,
+        program_id: &str,
+        discriminator_data: &[u8],
+        count: u64,
+        spaces: &[u64],
+    ) -> Result<(), DatabaseError> {
+        let key = Self::discriminator_key(program_id, DiscriminatorKind::Account, &hex::encode(discriminator_data));
+        let write = "
+        UPDATE { _key: @key } WITH { account_count: @count, account_spaces: @spaces } IN Discriminators
+            RETURN { before: OLD, after: NEW }
+        ";
+
+        let mut bind_vars = HashMap::new();
+        bind_vars.insert("key", key.into());
+        bind_vars.insert("count", count.into());
+        bind_vars.insert("spaces", serde_json::to_value(spaces)?);
+
+        self.audited_write(write, bind_vars, "Discriminators", AuditAction::AccountCount, ONCHAIN_CONTRIBUTOR).await?;
+        Ok(())
+    }
+
+    // Function to store the layout, source and notes submitted for a discriminator
+    pub async fn update_discriminator_details(
+        &self,
+        program_id: &str,
+        kind: DiscriminatorKind,
+        discriminator_data: &[u8],
+        details: &DiscriminatorDetails,
+        actor: &str,
+    ) -> Result<(), DatabaseError> {
+        let write = "
+        FOR d IN Discriminators
+            FILTER d._key == @key AND d.official != true
+            UPDATE d WITH @details IN Discriminators
+            RETURN { before: OLD, after: NEW }
+        ";
+
+        let mut bind_vars = HashMap::new();
+        bind_vars.insert("key", Self::discriminator_key(program_id, kind, &hex::encode(discriminator_data)).into());
+        bind_vars.insert("details", serde_json::to_value(details)?);
+
+        self.audited_write(write, bind_vars, "Discriminators", AuditAction::UpdateDetails, actor).await?;
+        Ok(())
+    }
+
+    // Function to get a single discriminator of a program
+    pub async fn get_discriminator(
+        &self,
+        program_id: &str,
+        kind: DiscriminatorKind,
+        discriminator_data: &[u8],
+    ) -> Result<Option<Discriminator>, DatabaseError> {
+        let aql = "
+        FOR d IN Discriminators
+            FILTER d._key == @key
+            RETURN d
+        ";
+
+        let mut bind_vars = HashMap::new();
+        bind_vars.insert("key", Self::discriminator_key(program_id, kind, &hex::encode(discriminator_data)).into());
+
+        let results: Vec<Discriminator> = self.db.aql_bind_vars(aql, bind_vars).await
+            .map_err(|e| DatabaseError::AqlQueryError { query: aql.to_string(), source: e })?;
+        Ok(results.into_iter().next().map(Discriminator::with_confidence))
+    }
+
+    // Function to query the discriminators of one kind of a program with up to `sample_limit` sample payloads each
+    pub async fn query_events(
+        &self,
+        program_id: &str,
+        kind: DiscriminatorKind,
+        sample_limit: usize,
+    ) -> Result<Vec<EventDiscriminator>, DatabaseError> {
+        let aql = "
+        FOR d IN Discriminators
+            FILTER d._key LIKE @program_id
+            FILTER d.kind == @kind OR (@kind == 'instruction' AND d.kind == null)
+            LET samples = (
+                FOR m IN MappedTo
+                    FILTER m._from == d._id
+                    LET i = DOCUMENT(m._to)
+                    FILTER i != null
+                    LIMIT @sample_limit
+                    RETURN DISTINCT i.instruction_data
+            )
+            RETURN { discriminator: d, samples: samples }
+        ";
+
+        let mut bind_vars = HashMap::new();
+        bind_vars.insert("program_id", format!("{}%", program_id).into());
+        bind_vars.insert("kind", kind.as_str().into());
+        bind_vars.insert("sample_limit", sample_limit.into());
+
+        let events: Vec<EventDiscriminator> = self.db.aql_bind_vars(aql, bind_vars).await
+            .map_err(|e| DatabaseError::AqlQueryError { query: aql.to_string(), source: e })?;
+        Ok(events
+            .into_iter()
+            .map(|event| EventDiscriminator { discriminator: event.discriminator.with_confidence(), ..event })
+            .collect())
+    }
+
+    // Function to attach a recovered name to a discriminator, recording where it came from.
+    // A verified name is never replaced by an unverified one.
+    pub async fn name_discriminator(
+        &self,
+        program_id: &str,
+        kind: DiscriminatorKind,
+        discriminator_data: &[u8],
+        name: DiscriminatorName,
+        provenance: Provenance,
+        actor: &str,
+    ) -> Result<(), DatabaseError> {
+        let write = "
+        FOR d IN Discriminators
+            FILTER d._key == @key AND d.official != true
+            FILTER d.name == null OR d.name.verified != true OR @name.verified == true
+            LET recorded = (
+                FOR p IN d.provenance || []
+                    FILTER p.source == @provenance.source AND p.component == @provenance.component
+                    RETURN 1
+            )
+            UPDATE d WITH {
+                name: @name,
+                provenance: recorded == [] ? PUSH(d.provenance || [], @provenance) : d.provenance
+            } IN Discriminators
+            RETURN { before: OLD, after: NEW }
+        ";
+
+        let mut bind_vars = HashMap::new();
+        bind_vars.insert("key", Self::discriminator_key(program_id, kind, &hex::encode(discriminator_data)).into());
+        bind_vars.insert("name", serde_json::to_value(name)?);
+        bind_vars.insert("provenance", serde_json::to_value(provenance)?);
+
+        self.audited_write(write, bind_vars, "Discriminators", AuditAction::Name, actor).await?;
+        Ok(())
+    }
+
+    // Function to get every distinct name recorded in the directory
+    pub async fn get_all_names(&self) -> Result<Vec<String>, DatabaseError> {
+        let aql = "FOR d IN Discriminators FILTER d.name != null RETURN DISTINCT d.name.name";
+        self.db.aql_str(aql).await
+            .map_err(|e| DatabaseError::AqlQueryError { query: aql.to_string(), source: e })
+    }
+
+    // Function to get every entry that has no name yet
+    pub async fn query_unnamed_discriminators(&self) -> Result<Vec<UnnamedDiscriminator>, DatabaseError> {
+        let aql = "
+        FOR d IN Discriminators
+            FILTER d.name == null
+            RETURN {
+                program_id: FIRST(SPLIT(d._key, '_')),
+                kind: d.kind,
+                discriminator_data: d.discriminator_data
+            }
+        ";
+        self.db.aql_str(aql).await
+            .map_err(|e| DatabaseError::AqlQueryError { query: aql.to_string(), source: e })
+    }
+
     
     // Function to get all program IDs from the database
     pub async fn get_all_program_ids(&self) -> Result<Vec<String>, DatabaseError> {
@@ -320,4 +1135,658 @@ impl GraphDatabase {
             .map_err(|e| DatabaseError::AqlQueryError { query: aql.to_string(), source: e })?;
         Ok(program_ids)
     }
+
+    // Function to make sure a user document exists for a verified wallet
+    pub async fn ensure_user(&self, user_id: &str) -> Result<(), DatabaseError> {
+        let user = User {
+            _key: user_id.to_string(),
+            id: user_id.to_string(),
+            role: None,
+        };
+        self.audited_upsert("Users", &user, AuditAction::CreateUser, user_id).await
+    }
+
+    // Function to store a new session for a user, valid for `ttl_secs` seconds
+    pub async fn create_session(&self, token: &str, user_id: &str, ttl_secs: u64) -> Result<(), DatabaseError> {
+        let now = unix_timestamp();
+        let session = Session {
+            _key: Self::hash_key(token),
+            user_id: user_id.to_string(),
+            created_at: now,
+            expires_at: now + ttl_secs,
+        };
+        self.upsert_document("Sessions", &session).await
+    }
+
+    // Function to get the user a bearer token belongs to, if the session exists and has not expired
+    pub async fn get_session_user(&self, token: &str) -> Result<Option<String>, DatabaseError> {
+        let aql = "
+        FOR s IN Sessions
+            FILTER s._key == @key AND s.expires_at > @now
+            RETURN s.user_id
+        ";
+
+        let mut bind_vars = HashMap::new();
+        bind_vars.insert("key", Self::hash_key(token).into());
+        bind_vars.insert("now", unix_timestamp().into());
+
+        let results: Vec<String> = self.db.aql_bind_vars(aql, bind_vars).await
+            .map_err(|e| DatabaseError::AqlQueryError { query: aql.to_string(), source: e })?;
+        Ok(results.into_iter().next())
+    }
+
+    // Function to end a session, and clear out any sessions that have expired
+    pub async fn delete_session(&self, token: &str) -> Result<(), DatabaseError> {
+        let aql = "
+        FOR s IN Sessions
+            FILTER s._key == @key OR s.expires_at <= @now
+            REMOVE s IN Sessions
+        ";
+
+        let mut bind_vars = HashMap::new();
+        bind_vars.insert("key", Self::hash_key(token).into());
+        bind_vars.insert("now", unix_timestamp().into());
+
+        let _: Vec<serde_json::Value> = self.db.aql_bind_vars(aql, bind_vars).await
+            .map_err(|e| DatabaseError::AqlQueryError { query: aql.to_string(), source: e })?;
+        Ok(())
+    }
+
+    // Function to store a newly issued API key
+    pub async fn create_api_key(&self, api_key: &ApiKey) -> Result<(), DatabaseError> {
+        self.upsert_document("ApiKeys", api_key).await
+    }
+
+    // Function to look up an API key by the hash of its secret, revoked or not
+    pub async fn get_api_key_by_hash(&self, key_hash: &str) -> Result<Option<ApiKey>, DatabaseError> {
+        let aql = "
+        FOR k IN ApiKeys
+            FILTER k.key_hash == @key_hash
+            LIMIT 1
+            RETURN k
+        ";
+
+        let mut bind_vars = HashMap::new();
+        bind_vars.insert("key_hash", key_hash.into());
+
+        let results: Vec<ApiKey> = self.db.aql_bind_vars(aql, bind_vars).await
+            .map_err(|e| DatabaseError::AqlQueryError { query: aql.to_string(), source: e })?;
+        Ok(results.into_iter().next())
+    }
+
+    // Function to list the API keys of a user, newest first
+    pub async fn list_api_keys(&self, user_id: &str) -> Result<Vec<ApiKey>, DatabaseError> {
+        let aql = "
+        FOR k IN ApiKeys
+            FILTER k.user_id == @user_id
+            SORT k.created_at DESC
+            RETURN k
+        ";
+
+        let mut bind_vars = HashMap::new();
+        bind_vars.insert("user_id", user_id.into());
+
+        self.db.aql_bind_vars(aql, bind_vars).await
+            .map_err(|e| DatabaseError::AqlQueryError { query: aql.to_string(), source: e })
+    }
+
+    // Function to revoke one of a user's API keys. Returns the key if it existed and was still active.
+    pub async fn revoke_api_key(&self, id: &str, user_id: &str) -> Result<Option<ApiKey>, DatabaseError> {
+        let aql = "
+        FOR k IN ApiKeys
+            FILTER k._key == @id AND k.user_id == @user_id AND k.revoked_at == null
+            UPDATE k WITH { revoked_at: @now } IN ApiKeys
+            RETURN NEW
+        ";
+
+        let mut bind_vars = HashMap::new();
+        bind_vars.insert("id", id.into());
+        bind_vars.insert("user_id", user_id.into());
+        bind_vars.insert("now", unix_timestamp().into());
+
+        let results: Vec<ApiKey> = self.db.aql_bind_vars(aql, bind_vars).await
+            .map_err(|e| DatabaseError::AqlQueryError { query: aql.to_string(), source: e })?;
+        Ok(results.into_iter().next())
+    }
+
+    // Function to hash an API key or session token for storage
+    pub fn hash_secret(secret: &str) -> String {
+        Self::hash_key(secret)
+    }
+
+    // Function to get the role of a user; users without an assigned role are contributors
+    pub async fn get_user_role(&self, user_id: &str) -> Result<Role, DatabaseError> {
+        let aql = "
+        FOR u IN Users
+            FILTER u._key == @user_id
+            RETURN u.role
+        ";
+
+        let mut bind_vars = HashMap::new();
+        bind_vars.insert("user_id", user_id.into());
+
+        let results: Vec<Option<Role>> = self.db.aql_bind_vars(aql, bind_vars).await
+            .map_err(|e| DatabaseError::AqlQueryError { query: aql.to_string(), source: e })?;
+        Ok(results.into_iter().next().flatten().unwrap_or_default())
+    }
+
+    // Function to assign a role to a user, creating the user if needed
+    pub async fn set_user_role(&self, user_id: &str, role: Role, actor: &str) -> Result<(), DatabaseError> {
+        let user = User {
+            _key: user_id.to_string(),
+            id: user_id.to_string(),
+            role: Some(role),
+        };
+        self.audited_upsert("Users", &user, AuditAction::SetRole, actor).await
+    }
+
+    // Function to store a submission
+    pub async fn create_submission(&self, submission: &Submission) -> Result<(), DatabaseError> {
+        self.audited_upsert("Submissions", submission, AuditAction::Submit, &submission.user_id).await
+    }
+
+    // Function to get a single submission
+    pub async fn get_submission(&self, id: &str) -> Result<Option<Submission>, DatabaseError> {
+        let aql = "
+        FOR s IN Submissions
+            FILTER s._key == @id
+            RETURN s
+        ";
+
+        let mut bind_vars = HashMap::new();
+        bind_vars.insert("id", id.into());
+
+        let results: Vec<Submission> = self.db.aql_bind_vars(aql, bind_vars).await
+            .map_err(|e| DatabaseError::AqlQueryError { query: aql.to_string(), source: e })?;
+        Ok(results.into_iter().next())
+    }
+
+    // Function to list submissions, optionally only those of one status or one user, oldest first
+    pub async fn list_submissions(
+        &self,
+        status: Option<SubmissionStatus>,
+        user_id: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<Submission>, DatabaseError> {
+        let aql = "
+        FOR s IN Submissions
+            FILTER @status == null OR s.status == @status
+            FILTER @user_id == null OR s.user_id == @user_id
+            SORT s.created_at ASC
+            LIMIT @limit
+            RETURN s
+        ";
+
+        let mut bind_vars = HashMap::new();
+        bind_vars.insert("status", serde_json::to_value(status)?);
+        bind_vars.insert("user_id", serde_json::to_value(user_id)?);
+        bind_vars.insert("limit", limit.into());
+
+        self.db.aql_bind_vars(aql, bind_vars).await
+            .map_err(|e| DatabaseError::AqlQueryError { query: aql.to_string(), source: e })
+    }
+
+    // Function to move a submission from one review state to another, as a compare-and-swap on its status.
+    // Returns the updated submission, or `None` if it does not exist or is no longer in the `from` state.
+    pub async fn review_submission(
+        &self,
+        id: &str,
+        from: SubmissionStatus,
+        status: SubmissionStatus,
+        reviewer: &str,
+        note: Option<&str>,
+    ) -> Result<Option<Submission>, DatabaseError> {
+        let write = "
+        FOR s IN Submissions
+            FILTER s._key == @id AND s.status == @from
+            UPDATE s WITH { status: @status, reviewed_by: @reviewer, reviewed_at: @now, review_note: @note } IN Submissions
+            RETURN { before: OLD, after: NEW }
+        ";
+
+        let mut bind_vars = HashMap::new();
+        bind_vars.insert("id", id.into());
+        bind_vars.insert("from", serde_json::to_value(from)?);
+        bind_vars.insert("status", serde_json::to_value(status)?);
+        bind_vars.insert("reviewer", reviewer.into());
+        bind_vars.insert("now", unix_timestamp().into());
+        bind_vars.insert("note", serde_json::to_value(note)?);
+
+        let action = match status {
+            SubmissionStatus::Rejected => AuditAction::Reject,
+            SubmissionStatus::Pending => AuditAction::Release,
+            _ => AuditAction::Approve,
+        };
+        let results = self.audited_write(write, bind_vars, "Submissions", action, reviewer).await?;
+        results.into_iter().next().map(serde_json::from_value).transpose().map_err(Into::into)
+    }
+
+    // Function to count a user's approved submissions, the basis for trusting their future ones
+    pub async fn count_approved_submissions(&self, user_id: &str) -> Result<u64, DatabaseError> {
+        let aql = "
+        RETURN LENGTH(
+            FOR s IN Submissions
+                FILTER s.user_id == @user_id AND s.status == 'approved'
+                RETURN 1
+        )
+        ";
+
+        let mut bind_vars = HashMap::new();
+        bind_vars.insert("user_id", user_id.into());
+
+        let results: Vec<u64> = self.db.aql_bind_vars(aql, bind_vars).await
+            .map_err(|e| DatabaseError::AqlQueryError { query: aql.to_string(), source: e })?;
+        Ok(results.into_iter().next().unwrap_or(0))
+    }
+
+    // Function to record that a user proved control of a program, replacing any earlier claim
+    pub async fn save_program_claim(&self, claim: &ProgramClaim) -> Result<(), DatabaseError> {
+        self.audited_upsert("ProgramClaims", claim, AuditAction::ClaimProgram, &claim.user_id).await
+    }
+
+    // Function to get the current claim on a program
+    pub async fn get_program_claim(&self, program_id: &str) -> Result<Option<ProgramClaim>, DatabaseError> {
+        let aql = "
+        FOR c IN ProgramClaims
+            FILTER c._key == @program_id
+            RETURN c
+        ";
+
+        let mut bind_vars = HashMap::new();
+        bind_vars.insert("program_id", program_id.into());
+
+        let results: Vec<ProgramClaim> = self.db.aql_bind_vars(aql, bind_vars).await
+            .map_err(|e| DatabaseError::AqlQueryError { query: aql.to_string(), source: e })?;
+        Ok(results.into_iter().next())
+    }
+
+    // Function to turn an entry into an official mapping, overwriting community supplied name and details
+    pub async fn mark_official(
+        &self,
+        program_id: &str,
+        kind: DiscriminatorKind,
+        discriminator_data: &[u8],
+        user_id: &str,
+        name: Option<DiscriminatorName>,
+        details: &DiscriminatorDetails,
+    ) -> Result<(), DatabaseError> {
+        let write = "
+        FOR d IN Discriminators
+            FILTER d._key == @key
+            UPDATE d WITH MERGE(
+                @details,
+                { official: true, official_by: @user_id, user_id: @user_id },
+                @name == null ? {} : { name: @name }
+            ) IN Discriminators
+            RETURN { before: OLD, after: NEW }
+        ";
+
+        let mut bind_vars = HashMap::new();
+        bind_vars.insert("key", Self::discriminator_key(program_id, kind, &hex::encode(discriminator_data)).into());
+        bind_vars.insert("details", serde_json::to_value(details)?);
+        bind_vars.insert("user_id", user_id.into());
+        bind_vars.insert("name", serde_json::to_value(name)?);
+
+        self.audited_write(write, bind_vars, "Discriminators", AuditAction::MarkOfficial, user_id).await?;
+        Ok(())
+    }
+
+    // Function to withdraw one of a user's own submissions that is still pending. Approved submissions are
+    // part of the directory, which other contributors may have built on, so they cannot be retracted.
+    // Returns the updated submission, or `None` if there was nothing to retract.
+    pub async fn retract_submission(&self, id: &str, user_id: &str) -> Result<Option<Submission>, DatabaseError> {
+        let write = "
+        FOR s IN Submissions
+            FILTER s._key == @id AND s.user_id == @user_id AND s.status == 'pending'
+            UPDATE s WITH { status: 'retracted', reviewed_at: @now } IN Submissions
+            RETURN { before: OLD, after: NEW }
+        ";
+
+        let mut bind_vars = HashMap::new();
+        bind_vars.insert("id", id.into());
+        bind_vars.insert("user_id", user_id.into());
+        bind_vars.insert("now", unix_timestamp().into());
+
+        let results = self.audited_write(write, bind_vars, "Submissions", AuditAction::Retract, user_id).await?;
+        results.into_iter().next().map(serde_json::from_value).transpose().map_err(Into::into)
+    }
+
+    // Function to get every submission of the given users with the names others had approved for the same
+    // discriminator. Only approved submissions count as competing names, so unreviewed ones cannot sway a score.
+    pub async fn get_submissions_with_competitors(
+        &self,
+        user_ids: &[String],
+    ) -> Result<Vec<SubmissionWithCompetitors>, DatabaseError> {
+        let aql = "
+        FOR s IN Submissions
+            FILTER s.user_id IN @user_ids
+            LET other_names = (
+                FOR o IN Submissions
+                    FILTER o.program_id == s.program_id AND o.kind == s.kind
+                    FILTER o.discriminator_data == s.discriminator_data
+                    FILTER o.user_id != s.user_id AND o.name != null
+                    FILTER o.status == 'approved'
+                    RETURN o.name
+            )
+            RETURN { submission: s, other_names: other_names }
+        ";
+
+        let mut bind_vars = HashMap::new();
+        bind_vars.insert("user_ids", serde_json::to_value(user_ids)?);
+
+        self.db.aql_bind_vars(aql, bind_vars).await
+            .map_err(|e| DatabaseError::AqlQueryError { query: aql.to_string(), source: e })
+    }
+
+    // Function to get the approved submissions competing to name the same discriminator,
+    // together with the pending ones when `include_pending` is set
+    pub async fn get_competing_submissions(
+        &self,
+        program_id: &str,
+        kind: DiscriminatorKind,
+        discriminator_data: &[u8],
+        include_pending: bool,
+    ) -> Result<Vec<Submission>, DatabaseError> {
+        let aql = "
+        FOR s IN Submissions
+            FILTER s.program_id == @program_id AND s.kind == @kind AND s.discriminator_data == @discriminator_data
+            FILTER s.name != null AND (s.status == 'approved' OR (@include_pending AND s.status == 'pending'))
+            SORT s.created_at ASC
+            RETURN s
+        ";
+
+        let mut bind_vars = HashMap::new();
+        bind_vars.insert("include_pending", include_pending.into());
+        bind_vars.insert("program_id", program_id.into());
+        bind_vars.insert("kind", kind.as_str().into());
+        bind_vars.insert("discriminator_data", serde_json::to_value(discriminator_data)?);
+
+        self.db.aql_bind_vars(aql, bind_vars).await
+            .map_err(|e| DatabaseError::AqlQueryError { query: aql.to_string(), source: e })
+    }
+
+    // Function to get the directory entries a user is recorded as contributor of, through `ContributedBy` edges
+    pub async fn get_contributions(&self, user_id: &str, limit: usize) -> Result<Vec<Discriminator>, DatabaseError> {
+        let aql = "
+        FOR c IN ContributedBy
+            FILTER c._to == CONCAT('Users/', @user_id)
+            LET d = DOCUMENT(c._from)
+            FILTER d != null
+            LIMIT @limit
+            RETURN d
+        ";
+
+        let mut bind_vars = HashMap::new();
+        bind_vars.insert("user_id", user_id.into());
+        bind_vars.insert("limit", limit.into());
+
+        let contributions: Vec<Discriminator> = self.db.aql_bind_vars(aql, bind_vars).await
+            .map_err(|e| DatabaseError::AqlQueryError { query: aql.to_string(), source: e })?;
+        Ok(contributions.into_iter().map(Discriminator::with_confidence).collect())
+    }
+
+    // Function to add a job to the queue. A job with a dedup key is only added if no queued or running job
+    // has the same key; otherwise that job is returned. Returns the job and whether it was added.
+    // The unique index on `active_key` decides which of two concurrent inserts wins, so both share one job.
+    pub async fn enqueue_job(&self, job: &Job) -> Result<(Job, bool), DatabaseError> {
+        let insert = "
+        INSERT @job INTO Jobs OPTIONS { ignoreErrors: @ignore_errors }
+            RETURN NEW
+        ";
+        let active = "
+        FOR j IN Jobs
+            FILTER j.active_key == @active_key
+            RETURN j
+        ";
+
+        for _ in 0..ENQUEUE_ATTEMPTS {
+            // Only a collision on `active_key` may be ignored; a job without one is always inserted
+            let mut bind_vars = HashMap::new();
+            bind_vars.insert("job", serde_json::to_value(job)?);
+            bind_vars.insert("ignore_errors", job.active_key.is_some().into());
+
+            let inserted: Vec<Job> = self.db.aql_bind_vars(insert, bind_vars).await
+                .map_err(|e| DatabaseError::AqlQueryError { query: insert.to_string(), source: e })?;
+            if let Some(inserted) = inserted.into_iter().next() {
+                return Ok((inserted, true));
+            }
+            let Some(active_key) = &job.active_key else { break };
+
+            // The insert only fails when an active job has the same key. That job may finish before it is
+            // read back, in which case the insert is tried again.
+            let mut bind_vars = HashMap::new();
+            bind_vars.insert("active_key", active_key.as_str().into());
+
+            let existing: Vec<Job> = self.db.aql_bind_vars(active, bind_vars).await
+                .map_err(|e| DatabaseError::AqlQueryError { query: active.to_string(), source: e })?;
+            if let Some(existing) = existing.into_iter().next() {
+                return Ok((existing, false));
+            }
+        }
+
+        Err(DatabaseError::SerializationError(serde::de::Error::custom("enqueue found neither a new nor an active job")))
+    }
+
+    // Function to take the oldest due job of one of `kinds` and mark it running
+    pub async fn claim_job(&self, kinds: &[JobKind]) -> Result<Option<Job>, DatabaseError> {
+        let aql = "
+        FOR j IN Jobs
+            FILTER j.state == 'queued' AND j.kind IN @kinds AND j.run_after <= @now
+            SORT j.run_after ASC, j.created_at ASC
+            LIMIT 1
+            UPDATE j WITH { state: 'running', attempts: j.attempts + 1, started_at: @now } IN Jobs
+            RETURN NEW
+        ";
+
+        let mut bind_vars = HashMap::new();
+        bind_vars.insert("kinds", serde_json::to_value(kinds)?);
+        bind_vars.insert("now", unix_timestamp().into());
+
+        let results: Vec<Job> = self.db.aql_bind_vars(aql, bind_vars).await
+            .map_err(|e| DatabaseError::AqlQueryError { query: aql.to_string(), source: e })?;
+        Ok(results.into_iter().next())
+    }
+
+    // Function to merge `patch` into a job while it is running.
+    // Returns false if the job is no longer running, e.g. because it was cancelled.
+    pub async fn update_running_job(&self, id: &str, patch: &serde_json::Value) -> Result<bool, DatabaseError> {
+        let aql = "
+        FOR j IN Jobs
+            FILTER j._key == @id AND j.state == 'running'
+            UPDATE j WITH @patch IN Jobs
+            RETURN 1
+        ";
+
+        let mut bind_vars = HashMap::new();
+        bind_vars.insert("id", id.into());
+        bind_vars.insert("patch", patch.clone());
+
+        let results: Vec<u8> = self.db.aql_bind_vars(aql, bind_vars).await
+            .map_err(|e| DatabaseError::AqlQueryError { query: aql.to_string(), source: e })?;
+        Ok(!results.is_empty())
+    }
+
+    // Function to get a single job
+    pub async fn get_job(&self, id: &str) -> Result<Option<Job>, DatabaseError> {
+        let aql = "
+        FOR j IN Jobs
+            FILTER j._key == @id
+            RETURN j
+        ";
+
+        let mut bind_vars = HashMap::new();
+        bind_vars.insert("id", id.into());
+
+        let results: Vec<Job> = self.db.aql_bind_vars(aql, bind_vars).await
+            .map_err(|e| DatabaseError::AqlQueryError { query: aql.to_string(), source: e })?;
+        Ok(results.into_iter().next())
+    }
+
+    // Function to get the most recent job with a dedup key, whatever its state
+    pub async fn latest_job(&self, dedup_key: &str) -> Result<Option<Job>, DatabaseError> {
+        let aql = "
+        FOR j IN Jobs
+            FILTER j.dedup_key == @dedup_key
+            SORT j.created_at DESC
+            LIMIT 1
+            RETURN j
+        ";
+
+        let mut bind_vars = HashMap::new();
+        bind_vars.insert("dedup_key", dedup_key.into());
+
+        let results: Vec<Job> = self.db.aql_bind_vars(aql, bind_vars).await
+            .map_err(|e| DatabaseError::AqlQueryError { query: aql.to_string(), source: e })?;
+        Ok(results.into_iter().next())
+    }
+
+    // Function to list jobs, newest first, optionally only those of one state or kind
+    pub async fn list_jobs(
+        &self,
+        state: Option<JobState>,
+        kind: Option<JobKind>,
+        limit: usize,
+    ) -> Result<Vec<Job>, DatabaseError> {
+        let aql = "
+        FOR j IN Jobs
+            FILTER @state == null OR j.state == @state
+            FILTER @kind == null OR j.kind == @kind
+            SORT j.created_at DESC
+            LIMIT @limit
+            RETURN j
+        ";
+
+        let mut bind_vars = HashMap::new();
+        bind_vars.insert("state", serde_json::to_value(state)?);
+        bind_vars.insert("kind", serde_json::to_value(kind)?);
+        bind_vars.insert("limit", limit.into());
+
+        self.db.aql_bind_vars(aql, bind_vars).await
+            .map_err(|e| DatabaseError::AqlQueryError { query: aql.to_string(), source: e })
+    }
+
+    // Function to cancel a job that has not finished. Returns the cancelled job, or `None` if there was nothing to cancel.
+    pub async fn cancel_job(&self, id: &str) -> Result<Option<Job>, DatabaseError> {
+        let aql = "
+        FOR j IN Jobs
+            FILTER j._key == @id AND j.state IN ['queued', 'running']
+            UPDATE j WITH { state: 'cancelled', active_key: null, finished_at: @now } IN Jobs
+            RETURN NEW
+        ";
+
+        let mut bind_vars = HashMap::new();
+        bind_vars.insert("id", id.into());
+        bind_vars.insert("now", unix_timestamp().into());
+
+        let results: Vec<Job> = self.db.aql_bind_vars(aql, bind_vars).await
+            .map_err(|e| DatabaseError::AqlQueryError { query: aql.to_string(), source: e })?;
+        Ok(results.into_iter().next())
+    }
+
+    // Function to put a dead or cancelled job back in the queue with a fresh set of attempts.
+    // Returns `None` if the job cannot be requeued or an equivalent job is already queued or running.
+    pub async fn requeue_job(&self, id: &str) -> Result<Option<Job>, DatabaseError> {
+        let aql = "
+        FOR j IN Jobs
+            FILTER j._key == @id AND j.state IN ['dead', 'cancelled']
+            LET duplicate = j.dedup_key != null AND LENGTH(
+                FOR o IN Jobs
+                    FILTER o.dedup_key == j.dedup_key AND o.state IN ['queued', 'running']
+                    RETURN 1
+            ) > 0
+            FILTER !duplicate
+            UPDATE j WITH {
+                state: 'queued', active_key: j.dedup_key, attempts: 0, run_after: @now, finished_at: null, stage: 'queued'
+            } IN Jobs OPTIONS { ignoreErrors: true }
+            RETURN NEW
+        ";
+
+        let mut bind_vars = HashMap::new();
+        bind_vars.insert("id", id.into());
+        bind_vars.insert("now", unix_timestamp().into());
+
+        let results: Vec<Job> = self.db.aql_bind_vars(aql, bind_vars).await
+            .map_err(|e| DatabaseError::AqlQueryError { query: aql.to_string(), source: e })?;
+        Ok(results.into_iter().next())
+    }
+
+    // Function to put jobs that were running when the process stopped back in the queue.
+    // The interrupted attempt still counts. Returns the number of jobs requeued.
+    pub async fn requeue_interrupted_jobs(&self) -> Result<u64, DatabaseError> {
+        let aql = "
+        FOR j IN Jobs
+            FILTER j.state == 'running'
+            UPDATE j WITH { state: 'queued', run_after: @now, stage: 'interrupted' } IN Jobs
+            RETURN 1
+        ";
+
+        let mut bind_vars = HashMap::new();
+        bind_vars.insert("now", unix_timestamp().into());
+
+        let results: Vec<u8> = self.db.aql_bind_vars(aql, bind_vars).await
+            .map_err(|e| DatabaseError::AqlQueryError { query: aql.to_string(), source: e })?;
+        Ok(results.len() as u64)
+    }
+
+    // Function to delete succeeded and cancelled jobs that finished more than `max_age_secs` ago.
+    // Dead jobs are kept until they are requeued. Returns the pruned jobs of the given kind.
+    pub async fn prune_finished_jobs(&self, max_age_secs: u64, kind: JobKind) -> Result<Vec<String>, DatabaseError> {
+        let aql = "
+        LET removed = (
+            FOR j IN Jobs
+                FILTER j.state IN ['succeeded', 'cancelled'] AND j.finished_at < @cutoff
+                REMOVE j IN Jobs
+                RETURN OLD
+        )
+        FOR j IN removed
+            FILTER j.kind == @kind
+            RETURN j._key
+        ";
+
+        let mut bind_vars = HashMap::new();
+        bind_vars.insert("cutoff", unix_timestamp().saturating_sub(max_age_secs).into());
+        bind_vars.insert("kind", kind.as_str().into());
+
+        let results: Vec<String> = self.db.aql_bind_vars(aql, bind_vars).await
+            .map_err(|e| DatabaseError::AqlQueryError { query: aql.to_string(), source: e })?;
+        Ok(results)
+    }
+
+    // Function to query the audit log, newest first, or oldest first when `oldest_first` is set.
+    // Pages continue after `filter.cursor` in the requested order.
+    pub async fn list_audit_events(
+        &self,
+        filter: &AuditFilter,
+        oldest_first: bool,
+        limit: usize,
+    ) -> Result<Vec<AuditEvent>, DatabaseError> {
+        let (comparison, direction) = if oldest_first { (">", "ASC") } else { ("<", "DESC") };
+        let aql = format!("
+        FOR e IN AuditLog
+            FILTER @actor == null OR e.actor == @actor
+            FILTER @action == null OR e.action == @action
+            FILTER @collection == null OR e.collection == @collection
+            FILTER @document_key == null OR e.document_key == @document_key
+            FILTER @request_id == null OR e.request_id == @request_id
+            FILTER @since == null OR e.timestamp >= @since
+            FILTER @until == null OR e.timestamp < @until
+            FILTER @cursor == null OR e._key {} @cursor
+            SORT e._key {}
+            LIMIT @limit
+            RETURN e
+        ", comparison, direction);
+
+        let mut bind_vars = HashMap::new();
+        bind_vars.insert("actor", serde_json::to_value(&filter.actor)?);
+        bind_vars.insert("action", serde_json::to_value(filter.action)?);
+        bind_vars.insert("collection", serde_json::to_value(&filter.collection)?);
+        bind_vars.insert("document_key", serde_json::to_value(&filter.document_key)?);
+        bind_vars.insert("request_id", serde_json::to_value(&filter.request_id)?);
+        bind_vars.insert("since", serde_json::to_value(filter.since)?);
+        bind_vars.insert("until", serde_json::to_value(filter.until)?);
+        bind_vars.insert("cursor", serde_json::to_value(&filter.cursor)?);
+        bind_vars.insert("limit", limit.into());
+
+        self.db.aql_bind_vars(&aql, bind_vars).await
+            .map_err(|e| DatabaseError::AqlQueryError { query: aql.clone(), source: e })
+    }
 }
@@ -0,0 +1,281 @@
+use actix_web::{web, HttpResponse, Responder};
+use flate2::read::ZlibDecoder;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use solana_sdk::pubkey::Pubkey;
+use std::io::Read;
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::anchor::discriminator_for;
+use crate::auth::AuthenticatedUser;
+use crate::decode::{resolve_defined_types, IdlField, IdlTypeDefinition};
+use crate::graph_disc::{
+    DatabaseError, DiscriminatorDetails, DiscriminatorKind, DiscriminatorName, GraphDatabase, JobSpec, Provenance,
+    ProvenanceSource, Role, ONCHAIN_CONTRIBUTOR,
+};
+use crate::jobs::{job_accepted, JobRunner};
+use crate::solana_connection::SolanaConnection;
+use crate::validation::ProgramId;
+
+// Component recorded in the provenance of entries imported from on-chain IDLs
+pub const IDL_FETCH_COMPONENT: &str = "idl_fetch";
+// Seed Anchor derives the IDL account address with
+const IDL_SEED: &str = "anchor:idl";
+// Account discriminator, authority and data length precede the compressed IDL
+const IDL_ACCOUNT_HEADER_SIZE: usize = 8 + 32 + 4;
+// Upper bound on a decompressed IDL. Anyone can write an IDL account, so a small account could otherwise
+// decompress into gigabytes.
+const MAX_IDL_JSON_BYTES: u64 = 8 * 1024 * 1024;
+
+#[derive(Error, Debug)]
+pub enum IdlError {
+    #[error("IDL account is truncated")]
+    Truncated,
+
+    #[error("Decompressed IDL is larger than {MAX_IDL_JSON_BYTES} bytes")]
+    TooLarge,
+
+    #[error("Failed to decompress IDL: {0}")]
+    Decompress(#[from] std::io::Error),
+
+    #[error("IDL is not valid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+// The parts of an Anchor IDL the directory imports. Both the legacy format and the 0.30 format,
+// which lists each discriminator explicitly, are accepted.
+#[derive(Debug, Deserialize)]
+struct Idl {
+    #[serde(default)]
+    instructions: Vec<IdlItem>,
+    #[serde(default)]
+    accounts: Vec<IdlItem>,
+    #[serde(default)]
+    events: Vec<IdlItem>,
+    // Kept loosely typed, so definitions of kinds the decoder does not know do not fail the import
+    #[serde(default)]
+    types: Vec<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct IdlItem {
+    name: String,
+    #[serde(default)]
+    discriminator: Option<Vec<u8>>,
+    #[serde(default)]
+    args: Vec<Value>,
+    #[serde(default)]
+    accounts: Vec<Value>,
+}
+
+// A discriminator an IDL names, with what it says about the instruction
+#[derive(Debug, Clone)]
+pub struct IdlEntry {
+    pub kind: DiscriminatorKind,
+    pub discriminator: Vec<u8>,
+    pub name: String,
+    pub args: Option<Vec<IdlField>>,
+    pub accounts: Option<Vec<String>>,
+}
+
+// An IDL read from the chain
+#[derive(Debug)]
+pub struct OnchainIdl {
+    pub address: String,
+    // sha256 of the decompressed IDL, recorded as the provenance reference
+    pub hash: String,
+    pub entries: Vec<IdlEntry>,
+}
+
+// Function to derive the address of the account Anchor stores a program's IDL in
+pub fn idl_address(program_id: &Pubkey) -> Result<Pubkey, String> {
+    let (base, _) = Pubkey::find_program_address(&[], program_id);
+    Pubkey::create_with_seed(&base, IDL_SEED, program_id).map_err(|e| e.to_string())
+}
+
+// Function to get the IDL JSON out of the data of an IDL account
+pub fn decode_idl_account(data: &[u8]) -> Result<Vec<u8>, IdlError> {
+    let length = data
+        .get(IDL_ACCOUNT_HEADER_SIZE - 4..IDL_ACCOUNT_HEADER_SIZE)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+        .ok_or(IdlError::Truncated)?;
+    let compressed = IDL_ACCOUNT_HEADER_SIZE
+        .checked_add(length)
+        .and_then(|end| data.get(IDL_ACCOUNT_HEADER_SIZE..end))
+        .ok_or(IdlError::Truncated)?;
+
+    // One byte past the cap is read to tell an IDL of exactly the maximum size from a larger one
+    let mut json = Vec::new();
+    ZlibDecoder::new(compressed).take(MAX_IDL_JSON_BYTES + 1).read_to_end(&mut json)?;
+    if json.len() as u64 > MAX_IDL_JSON_BYTES {
+        return Err(IdlError::TooLarge);
+    }
+    Ok(json)
+}
+
+// Function to collect the account names of an instruction, flattening nested account groups
+fn account_names(accounts: &[Value], names: &mut Vec<String>) {
+    for account in accounts {
+        match account.get("accounts").and_then(Value::as_array) {
+            Some(group) => account_names(group, names),
+            None => {
+                if let Some(name) = account.get("name").and_then(Value::as_str) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+    }
+}
+
+// Function to list the discriminators an IDL names. Entries without an explicit discriminator
+// get the one Anchor derives from their name.
+pub fn parse_idl(json: &[u8]) -> Result<Vec<IdlEntry>, IdlError> {
+    let idl: Idl = serde_json::from_slice(json)?;
+    let types: Vec<IdlTypeDefinition> = idl
+        .types
+        .into_iter()
+        .filter_map(|definition| serde_json::from_value(definition).ok())
+        .collect();
+
+    let sections = [
+        (DiscriminatorKind::Instruction, idl.instructions),
+        (DiscriminatorKind::Account, idl.accounts),
+        (DiscriminatorKind::Event, idl.events),
+    ];
+
+    let mut entries = Vec::new();
+    for (kind, items) in sections {
+        for item in items {
+            let discriminator = match item.discriminator {
+                Some(discriminator) if !discriminator.is_empty() => discriminator,
+                _ => discriminator_for(kind, &item.name).to_vec(),
+            };
+
+            let (args, accounts) = if kind == DiscriminatorKind::Instruction {
+                // Arguments of types the decoder does not know are left out; types the IDL defines are inlined
+                let args = serde_json::from_value::<Vec<IdlField>>(Value::Array(item.args))
+                    .ok()
+                    .map(|args| resolve_defined_types(&args, &types));
+                let mut names = Vec::new();
+                account_names(&item.accounts, &mut names);
+                (args, Some(names))
+            } else {
+                (None, None)
+            };
+
+            entries.push(IdlEntry {
+                kind,
+                discriminator,
+                name: item.name,
+                args,
+                accounts,
+            });
+        }
+    }
+
+    Ok(entries)
+}
+
+// Function to read and parse a program's on-chain IDL. Returns `None` if the program has not published one.
+pub async fn fetch_onchain_idl(solana_client: &SolanaConnection, program_id: &str) -> Result<Option<OnchainIdl>, String> {
+    let program = Pubkey::from_str(program_id).map_err(|e| e.to_string())?;
+    let address = idl_address(&program)?.to_string();
+
+    let account = solana_client.get_multiple_accounts(std::slice::from_ref(&address)).await?;
+    let Some(Some(account)) = account.into_iter().next() else {
+        return Ok(None);
+    };
+
+    let json = decode_idl_account(&account.data).map_err(|e| e.to_string())?;
+    let entries = parse_idl(&json).map_err(|e| e.to_string())?;
+
+    Ok(Some(OnchainIdl {
+        address,
+        hash: hex::encode(Sha256::digest(&json)),
+        entries,
+    }))
+}
+
+// Function to store an IDL entry with its name and details
+pub async fn store_idl_entry(db: &GraphDatabase, program_id: &str, entry: &IdlEntry, idl_hash: &str) -> Result<(), DatabaseError> {
+    let provenance = Provenance::new(ProvenanceSource::OnchainIdl, IDL_FETCH_COMPONENT, Some(idl_hash.to_string()));
+
+    db.upload_discriminator(
+        program_id,
+        entry.kind,
+        entry.discriminator.clone(),
+        Vec::new(),
+        ONCHAIN_CONTRIBUTOR,
+        provenance.clone(),
+    ).await?;
+
+    let details = DiscriminatorDetails {
+        args: entry.args.clone(),
+        accounts: entry.accounts.clone(),
+        source: Some("idl".to_string()),
+        notes: None,
+    };
+    db.update_discriminator_details(program_id, entry.kind, &entry.discriminator, &details, ONCHAIN_CONTRIBUTOR)
+        .await?;
+
+    let name = DiscriminatorName {
+        name: entry.name.clone(),
+        source: "idl".to_string(),
+        verified: discriminator_for(entry.kind, &entry.name).as_slice() == entry.discriminator.as_slice(),
+    };
+    db.name_discriminator(program_id, entry.kind, &entry.discriminator, name, provenance, ONCHAIN_CONTRIBUTOR)
+        .await
+}
+
+// Queues an import of the program's on-chain IDL, which the client polls as a job
+pub async fn fetch_idl_endpoint(
+    jobs: web::Data<JobRunner>,
+    user: AuthenticatedUser,
+    program_id: ProgramId,
+) -> impl Responder {
+    if let Err(denied) = user.require(Role::Contributor) {
+        return denied.into();
+    }
+
+    match jobs.enqueue(JobSpec::IdlFetch { program_id: program_id.into_inner() }, Some(user.user_id)).await {
+        Ok((job, added)) => job_accepted(&job, added),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    // Function to build the data of an IDL account holding `json`
+    fn idl_account(json: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(json).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut data = vec![0u8; IDL_ACCOUNT_HEADER_SIZE - 4];
+        data.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+        data.extend_from_slice(&compressed);
+        data
+    }
+
+    #[test]
+    fn decompresses_the_idl_of_an_account() {
+        let json = br#"{"instructions":[{"name":"initialize"}]}"#;
+        let entries = parse_idl(&decode_idl_account(&idl_account(json)).unwrap()).unwrap();
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].name, "initialize");
+    }
+
+    #[test]
+    fn rejects_idls_that_decompress_past_the_cap() {
+        let bomb = idl_account(&vec![b' '; MAX_IDL_JSON_BYTES as usize + 1]);
+        assert!(matches!(decode_idl_account(&bomb), Err(IdlError::TooLarge)));
+    }
+}
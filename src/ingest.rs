@@ -0,0 +1,241 @@
+use std::error::Error;
+
+use solana_sdk::message::VersionedMessage;
+use solana_sdk::pubkey::Pubkey;
+use solana_transaction_status::option_serializer::OptionSerializer;
+use solana_transaction_status::{EncodedConfirmedTransactionWithStatusMeta, UiInnerInstructions, UiInstruction};
+
+use crate::anchor::instruction_discriminator;
+use crate::graph_disc::{
+    DatabaseError, DiscriminatorKind, DiscriminatorName, GraphDatabase, Provenance, ProvenanceSource, ONCHAIN_CONTRIBUTOR,
+};
+use crate::log_parser::{align_invocations, parse_invocations};
+use crate::solana_connection::ListenerSink;
+
+// An instruction as it was executed inside a transaction, with its account indexes resolved
+#[derive(Debug, Clone)]
+pub struct ExecutedInstruction {
+    // Position of the top-level instruction this belongs to
+    pub index: usize,
+    // Position among the inner instructions of that top-level instruction, `None` for the top-level one itself
+    pub inner_index: Option<usize>,
+    pub program_id: String,
+    pub accounts: Vec<String>,
+    pub data: Vec<u8>,
+}
+
+// Function to split instruction or account data into its 8 byte discriminator and the remaining bytes
+pub fn split_discriminator(data: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+    if data.len() < 8 {
+        return None;
+    }
+    let (discriminator, rest) = data.split_at(8);
+    Some((discriminator.to_vec(), rest.to_vec()))
+}
+
+// Function to list the account keys a message can reference by index.
+// Static keys come first, followed by the writable and then the readonly addresses loaded from lookup tables.
+pub fn resolve_account_keys(message: &VersionedMessage, loaded_writable: &[String], loaded_readonly: &[String]) -> Vec<String> {
+    message
+        .static_account_keys()
+        .iter()
+        .map(Pubkey::to_string)
+        .chain(loaded_writable.iter().cloned())
+        .chain(loaded_readonly.iter().cloned())
+        .collect()
+}
+
+// Function to resolve the top-level instructions of a message against its account keys
+pub fn top_level_instructions(message: &VersionedMessage, account_keys: &[String]) -> Vec<ExecutedInstruction> {
+    let resolve = |index: u8| account_keys.get(index as usize).cloned();
+
+    message
+        .instructions()
+        .iter()
+        .enumerate()
+        .filter_map(|(index, instruction)| {
+            Some(ExecutedInstruction {
+                index,
+                inner_index: None,
+                program_id: resolve(instruction.program_id_index)?,
+                accounts: instruction.accounts.iter().filter_map(|i| resolve(*i)).collect(),
+                data: instruction.data.clone(),
+            })
+        })
+        .collect()
+}
+
+// Function to flatten a transaction into the instructions it executed, in execution order.
+// Each top-level instruction is followed by the inner instructions it invoked.
+pub fn executed_instructions(transaction: &EncodedConfirmedTransactionWithStatusMeta) -> Option<Vec<ExecutedInstruction>> {
+    let decoded = transaction.transaction.transaction.decode()?;
+    let meta = transaction.transaction.meta.as_ref();
+
+    let account_keys = match meta.map(|meta| &meta.loaded_addresses) {
+        Some(OptionSerializer::Some(loaded)) => resolve_account_keys(&decoded.message, &loaded.writable, &loaded.readonly),
+        _ => resolve_account_keys(&decoded.message, &[], &[]),
+    };
+
+    let inner_instructions = match meta.map(|meta| &meta.inner_instructions) {
+        Some(OptionSerializer::Some(inner)) => inner.as_slice(),
+        _ => &[],
+    };
+
+    Some(with_inner_instructions(&decoded.message, &account_keys, inner_instructions))
+}
+
+// Function to resolve the instructions of a message, each top-level one followed by the inner
+// instructions it invoked, as reported by the transaction meta or a simulation
+pub fn with_inner_instructions(
+    message: &VersionedMessage,
+    account_keys: &[String],
+    inner_instructions: &[UiInnerInstructions],
+) -> Vec<ExecutedInstruction> {
+    let resolve = |index: u8| account_keys.get(index as usize).cloned();
+
+    let mut executed = Vec::new();
+    for top_level in top_level_instructions(message, account_keys) {
+        let index = top_level.index;
+        executed.push(top_level);
+
+        let inner = inner_instructions
+            .iter()
+            .filter(|inner| inner.index as usize == index)
+            .flat_map(|inner| &inner.instructions);
+
+        for (inner_index, ui_instruction) in inner.enumerate() {
+            let UiInstruction::Compiled(compiled) = ui_instruction else {
+                continue;
+            };
+            let (Some(program_id), Ok(data)) = (
+                resolve(compiled.program_id_index),
+                bs58::decode(&compiled.data).into_vec(),
+            ) else {
+                continue;
+            };
+            executed.push(ExecutedInstruction {
+                index,
+                inner_index: Some(inner_index),
+                program_id,
+                accounts: compiled.accounts.iter().filter_map(|i| resolve(*i)).collect(),
+                data,
+            });
+        }
+    }
+
+    executed
+}
+
+// Function to get the log messages of a transaction, if the RPC returned them
+pub fn log_messages(transaction: &EncodedConfirmedTransactionWithStatusMeta) -> Vec<String> {
+    match transaction.transaction.meta.as_ref().map(|meta| &meta.log_messages) {
+        Some(OptionSerializer::Some(logs)) => logs.clone(),
+        _ => Vec::new(),
+    }
+}
+
+// Component recorded in the provenance of entries ingested from watched programs
+pub const LISTENER_COMPONENT: &str = "listener";
+
+// Function to store every discriminator the watched program executed or emitted in a transaction.
+// Instruction names Anchor logs are attached with provenance "logs", and events from `Program data:`
+// lines are stored with their payload as a sample. Every record references the transaction signature.
+// Returns the number of discriminators uploaded.
+pub async fn ingest_transaction(
+    db: &GraphDatabase,
+    program_id: &str,
+    signature: &str,
+    transaction: &EncodedConfirmedTransactionWithStatusMeta,
+) -> Result<usize, DatabaseError> {
+    let Some(instructions) = executed_instructions(transaction) else {
+        return Ok(0);
+    };
+
+    let invocations = parse_invocations(&log_messages(transaction));
+    let aligned = align_invocations(&instructions, &invocations);
+
+    let mut uploaded = 0;
+    for (instruction, invocation) in instructions.iter().zip(aligned) {
+        if instruction.program_id != program_id {
+            continue;
+        }
+        let Some((discriminator_data, instruction_data)) = split_discriminator(&instruction.data) else {
+            continue;
+        };
+
+        db.upload_discriminator(
+            program_id,
+            DiscriminatorKind::Instruction,
+            discriminator_data.clone(),
+            instruction_data,
+            ONCHAIN_CONTRIBUTOR,
+            Provenance::new(ProvenanceSource::Chain, LISTENER_COMPONENT, Some(signature.to_string())),
+        ).await?;
+        uploaded += 1;
+
+        if let Some(name) = invocation.and_then(|invocation| invocation.instruction_name.as_ref()) {
+            let verified = instruction_discriminator(name)[..] == discriminator_data[..];
+            db.name_discriminator(
+                program_id,
+                DiscriminatorKind::Instruction,
+                &discriminator_data,
+                DiscriminatorName {
+                    name: name.clone(),
+                    source: "logs".to_string(),
+                    verified,
+                },
+                Provenance::new(ProvenanceSource::Logs, LISTENER_COMPONENT, Some(signature.to_string())),
+                ONCHAIN_CONTRIBUTOR,
+            ).await?;
+        }
+    }
+
+    for invocation in invocations.iter().filter(|invocation| invocation.program_id == program_id) {
+        for payload in &invocation.data {
+            let Some((discriminator_data, sample)) = split_discriminator(payload) else {
+                continue;
+            };
+
+            db.upload_discriminator(
+                program_id,
+                DiscriminatorKind::Event,
+                discriminator_data,
+                sample,
+                ONCHAIN_CONTRIBUTOR,
+                Provenance::new(ProvenanceSource::Chain, LISTENER_COMPONENT, Some(signature.to_string())),
+            ).await?;
+            uploaded += 1;
+        }
+    }
+
+    Ok(uploaded)
+}
+
+impl ListenerSink for GraphDatabase {
+    async fn store_transaction(
+        &self,
+        program_id: &str,
+        signature: &str,
+        transaction: &EncodedConfirmedTransactionWithStatusMeta,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        ingest_transaction(self, program_id, signature, transaction).await?;
+        Ok(())
+    }
+
+    async fn store_account(
+        &self,
+        program_id: &str,
+        account: &str,
+        discriminator: Vec<u8>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.upload_discriminator(
+            program_id,
+            DiscriminatorKind::Account,
+            discriminator,
+            Vec::new(),
+            ONCHAIN_CONTRIBUTOR,
+            Provenance::new(ProvenanceSource::Chain, LISTENER_COMPONENT, Some(account.to_string())),
+        ).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,626 @@
+use actix_web::{web, HttpResponse, Responder};
+use futures::FutureExt;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
+use tokio::task::AbortHandle;
+
+use crate::audit::{current_request_id, with_request_id};
+use crate::auth::{random_hex, AuthenticatedUser};
+use crate::bytecode::{analyze_elf, store_candidate};
+use crate::graph_disc::{
+    unix_timestamp, AuditFilter, DatabaseError, DiscriminatorKind, GraphDatabase, Job, JobKind, JobSpec, JobState,
+    Provenance, ProvenanceSource, Role, BYTECODE_CONTRIBUTOR, ONCHAIN_CONTRIBUTOR,
+};
+use crate::idl::{fetch_onchain_idl, store_idl_entry};
+use crate::name_recovery::NameRecovery;
+use crate::solana_connection::SolanaConnection;
+use crate::validation::is_valid_pubkey;
+
+// Component recorded in the provenance of entries found by scanning program accounts
+const ACCOUNT_SCAN_COMPONENT: &str = "account_scan";
+// Directory finished audit exports are written to
+pub const AUDIT_EXPORT_DIR: &str = "exports";
+// Events fetched per query while writing an audit export
+const AUDIT_EXPORT_BATCH_SIZE: usize = 1000;
+
+// Attempts a job gets before it is moved to the dead-letter list
+const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+// Delay before the first retry, doubled after every further failure up to the maximum
+const RETRY_BASE_DELAY_SECS: u64 = 30;
+const MAX_RETRY_DELAY_SECS: u64 = 60 * 60;
+// How long succeeded and cancelled jobs can still be polled, in seconds
+const FINISHED_JOB_RETENTION_SECS: u64 = 7 * 24 * 60 * 60;
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+// How often the queue is checked for due jobs when nothing wakes the dispatcher
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+// Progress is written back every this many items
+const PROGRESS_INTERVAL: u64 = 25;
+
+const DEFAULT_JOB_LIST_SIZE: usize = 100;
+const MAX_JOB_LIST_SIZE: usize = 1000;
+
+const JOB_KINDS: [JobKind; 5] = [
+    JobKind::AccountScan,
+    JobKind::Backfill,
+    JobKind::BytecodeAnalysis,
+    JobKind::IdlFetch,
+    JobKind::AuditExport,
+];
+
+// Function to get how many jobs of a kind may run at once. Kinds that hammer the RPC are kept low.
+fn concurrency_limit(kind: JobKind) -> usize {
+    match kind {
+        JobKind::AccountScan => 2,
+        JobKind::Backfill => 2,
+        JobKind::BytecodeAnalysis => 1,
+        JobKind::IdlFetch => 4,
+        JobKind::AuditExport => 1,
+    }
+}
+
+// Function to compute how long to wait before retrying a job that has failed `attempts` times
+pub fn retry_delay(attempts: u32) -> u64 {
+    let doublings = attempts.saturating_sub(1).min(16);
+    (RETRY_BASE_DELAY_SECS << doublings).min(MAX_RETRY_DELAY_SECS)
+}
+
+// Function to build the key that keeps two jobs of the same kind from running for one program
+pub fn job_dedup_key(kind: JobKind, program_id: &str) -> String {
+    format!("{}:{}", kind.as_str(), program_id)
+}
+
+// Function to get the path an audit export job writes its file to
+pub fn audit_export_path(job_id: &str) -> String {
+    format!("{}/{}.ndjson", AUDIT_EXPORT_DIR, job_id)
+}
+
+// Writes the progress of a running job back to the store. Every write doubles as a cancellation
+// check: once the job is no longer running, the handler is told to stop.
+struct Progress<'a> {
+    db: &'a GraphDatabase,
+    id: &'a str,
+    reported: AtomicU64,
+}
+
+impl Progress<'_> {
+    async fn update(&self, patch: Value) -> Result<(), String> {
+        match self.db.update_running_job(self.id, &patch).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err("Job is no longer running".to_string()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    // Function to move the job to a new stage, with the number of items it will process if known
+    async fn stage(&self, stage: &str, total: Option<u64>) -> Result<(), String> {
+        self.reported.store(0, Ordering::Relaxed);
+        self.update(json!({"stage": stage, "processed": 0, "total": total})).await
+    }
+
+    // Function to record the number of items processed so far, written every `PROGRESS_INTERVAL` items
+    async fn processed(&self, processed: u64) -> Result<(), String> {
+        if processed.saturating_sub(self.reported.load(Ordering::Relaxed)) < PROGRESS_INTERVAL {
+            return Ok(());
+        }
+        self.reported.store(processed, Ordering::Relaxed);
+        self.update(json!({"processed": processed})).await
+    }
+}
+
+// Runs the jobs of the persistent queue. Jobs are stored before they run, so work that was queued or
+// interrupted by a restart is picked up again. Failed jobs are retried with exponential backoff until
+// they run out of attempts, at which point they stay in the dead-letter list until an admin requeues them.
+pub struct JobRunner {
+    db: Arc<GraphDatabase>,
+    // Background priority: queued bulk work yields the shared RPC budget to HTTP lookups
+    solana_client: Arc<SolanaConnection>,
+    recovery: Arc<NameRecovery>,
+    // One semaphore per kind, holding a permit for every job of that kind that may run at once
+    limits: HashMap<JobKind, Arc<Semaphore>>,
+    // Jobs running in this process, so they can be stopped when cancelled
+    running: Mutex<HashMap<String, AbortHandle>>,
+    // Signalled when a job is queued or a slot frees up
+    wake: Notify,
+}
+
+impl JobRunner {
+    pub fn new(db: Arc<GraphDatabase>, solana_client: Arc<SolanaConnection>, recovery: Arc<NameRecovery>) -> Self {
+        JobRunner {
+            db,
+            solana_client,
+            recovery,
+            limits: JOB_KINDS
+                .iter()
+                .map(|kind| (*kind, Arc::new(Semaphore::new(concurrency_limit(*kind)))))
+                .collect(),
+            running: Mutex::new(HashMap::new()),
+            wake: Notify::new(),
+        }
+    }
+
+    // Function to queue a job, or join the queued or running job of the same kind for the same program.
+    // Returns the job and whether it was newly queued.
+    pub async fn enqueue(&self, spec: JobSpec, requested_by: Option<String>) -> Result<(Job, bool), DatabaseError> {
+        let now = unix_timestamp();
+        let kind = spec.kind();
+        let dedup_key = spec.program_id().map(|program_id| job_dedup_key(kind, program_id));
+        let job = Job {
+            _key: random_hex(8),
+            kind,
+            active_key: dedup_key.clone(),
+            dedup_key,
+            spec,
+            state: JobState::Queued,
+            attempts: 0,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            run_after: now,
+            stage: "queued".to_string(),
+            processed: 0,
+            total: None,
+            result: None,
+            error: None,
+            requested_by,
+            // Writes made by the job are audited under the request that queued it
+            request_id: current_request_id(),
+            created_at: now,
+            started_at: None,
+            finished_at: None,
+        };
+
+        let (job, added) = self.db.enqueue_job(&job).await?;
+        if added {
+            println!("Queued {} job {}", job.kind.as_str(), job._key);
+            self.wake.notify_one();
+        }
+        Ok((job, added))
+    }
+
+    // Function to cancel a job, stopping it if it is running in this process.
+    // Returns `None` if the job had already finished.
+    pub async fn cancel(&self, id: &str) -> Result<Option<Job>, DatabaseError> {
+        let job = self.db.cancel_job(id).await?;
+        if job.is_some() {
+            if let Some(handle) = self.running.lock().unwrap().remove(id) {
+                handle.abort();
+            }
+            println!("Cancelled job {}", id);
+            self.wake.notify_one();
+        }
+        Ok(job)
+    }
+
+    // Function to give a dead or cancelled job a fresh set of attempts
+    pub async fn requeue(&self, id: &str) -> Result<Option<Job>, DatabaseError> {
+        let job = self.db.requeue_job(id).await?;
+        if job.is_some() {
+            println!("Requeued job {}", id);
+            self.wake.notify_one();
+        }
+        Ok(job)
+    }
+
+    // Function to start running queued jobs in the background. Jobs left running by the previous
+    // process are queued again first; the interrupted attempt counts towards their limit.
+    pub fn spawn(self: &Arc<Self>) {
+        let runner = self.clone();
+        tokio::spawn(async move {
+            match runner.db.requeue_interrupted_jobs().await {
+                Ok(requeued) if requeued > 0 => println!("Requeued {} interrupted jobs", requeued),
+                Ok(_) => {}
+                Err(e) => eprintln!("Failed to requeue interrupted jobs: {}", e),
+            }
+            runner.dispatch().await;
+        });
+    }
+
+    // Claims due jobs while their kind has a free slot and runs each in its own task
+    async fn dispatch(self: Arc<Self>) {
+        let mut next_prune = Instant::now();
+
+        loop {
+            if Instant::now() >= next_prune {
+                // Audit exports leave a file behind that is only reachable through the job
+                match self.db.prune_finished_jobs(FINISHED_JOB_RETENTION_SECS, JobKind::AuditExport).await {
+                    Ok(exports) => {
+                        for id in exports {
+                            if let Err(e) = tokio::fs::remove_file(audit_export_path(&id)).await {
+                                if e.kind() != std::io::ErrorKind::NotFound {
+                                    eprintln!("Failed to delete audit export {}: {}", id, e);
+                                }
+                            }
+                        }
+                    }
+                    Err(e) => eprintln!("Failed to prune finished jobs: {}", e),
+                }
+                next_prune = Instant::now() + PRUNE_INTERVAL;
+            }
+
+            let free: Vec<JobKind> = JOB_KINDS
+                .iter()
+                .copied()
+                .filter(|kind| self.limits[kind].available_permits() > 0)
+                .collect();
+
+            let claimed = if free.is_empty() {
+                None
+            } else {
+                match self.db.claim_job(&free).await {
+                    Ok(job) => job,
+                    Err(e) => {
+                        eprintln!("Failed to claim a job: {}", e);
+                        None
+                    }
+                }
+            };
+
+            match claimed {
+                Some(job) => {
+                    // Only the dispatcher takes permits, so the slot seen above is still free
+                    let permit = self.limits[&job.kind]
+                        .clone()
+                        .acquire_owned()
+                        .await
+                        .expect("job semaphores are never closed");
+
+                    let id = job._key.clone();
+                    let runner = self.clone();
+                    // The map stays locked until the handle is stored, so a job that finishes at once removes it after
+                    let mut running = self.running.lock().unwrap();
+                    let handle = tokio::spawn(async move { runner.execute(job, permit).await });
+                    running.insert(id, handle.abort_handle());
+                }
+                None => {
+                    tokio::select! {
+                        _ = self.wake.notified() => {}
+                        _ = tokio::time::sleep(POLL_INTERVAL) => {}
+                    }
+                }
+            }
+        }
+    }
+
+    // Runs one attempt of a job and records how it ended, including if it panicked
+    async fn execute(&self, job: Job, permit: OwnedSemaphorePermit) {
+        println!(
+            "Running {} job {} (attempt {} of {})",
+            job.kind.as_str(),
+            job._key,
+            job.attempts,
+            job.max_attempts
+        );
+
+        let outcome = with_request_id(job.request_id.clone(), AssertUnwindSafe(self.run(&job)).catch_unwind()).await;
+
+        let patch = match outcome {
+            Ok(Ok(result)) => {
+                println!("{} job {} succeeded", job.kind.as_str(), job._key);
+                json!({
+                    "state": JobState::Succeeded,
+                    "active_key": null,
+                    "stage": "done",
+                    "result": result,
+                    "error": null,
+                    "finished_at": unix_timestamp(),
+                })
+            }
+            Ok(Err(e)) => Self::failure(&job, e),
+            Err(_) => Self::failure(&job, "Job panicked".to_string()),
+        };
+
+        // Nothing is recorded for a job that was cancelled while it ran
+        if let Err(e) = self.db.update_running_job(&job._key, &patch).await {
+            eprintln!("Failed to record the outcome of job {}: {}", job._key, e);
+        }
+
+        self.running.lock().unwrap().remove(&job._key);
+        drop(permit);
+        self.wake.notify_one();
+    }
+
+    // Function to build the update for a failed attempt: a retry after a backoff, or the dead-letter list
+    fn failure(job: &Job, error: String) -> Value {
+        if job.attempts < job.max_attempts {
+            let delay = retry_delay(job.attempts);
+            eprintln!(
+                "{} job {} failed on attempt {} of {}, retrying in {}s: {}",
+                job.kind.as_str(),
+                job._key,
+                job.attempts,
+                job.max_attempts,
+                delay,
+                error
+            );
+            json!({
+                "state": JobState::Queued,
+                "stage": "waiting to retry",
+                "run_after": unix_timestamp() + delay,
+                "error": error,
+            })
+        } else {
+            eprintln!(
+                "{} job {} failed {} times and was moved to the dead-letter list: {}",
+                job.kind.as_str(),
+                job._key,
+                job.attempts,
+                error
+            );
+            json!({
+                "state": JobState::Dead,
+                "active_key": null,
+                "stage": "failed",
+                "error": error,
+                "finished_at": unix_timestamp(),
+            })
+        }
+    }
+
+    // Function to run a job's handler and return its result
+    async fn run(&self, job: &Job) -> Result<Value, String> {
+        let progress = Progress {
+            db: &self.db,
+            id: &job._key,
+            reported: AtomicU64::new(0),
+        };
+
+        match &job.spec {
+            JobSpec::AccountScan { program_id } => self.scan_accounts(program_id, &progress).await,
+            JobSpec::Backfill { program_id, until } => self.backfill(program_id, until.as_deref(), &progress).await,
+            JobSpec::BytecodeAnalysis { program_id } => self.analyze_bytecode(program_id, &progress).await,
+            JobSpec::IdlFetch { program_id } => self.fetch_idl(program_id, &progress).await,
+            JobSpec::AuditExport { filter } => self.export_audit_events(&job._key, filter.clone(), &progress).await,
+        }
+    }
+
+    // Function to fetch the discriminator prefix of every program account and store each distinct one with its count
+    async fn scan_accounts(&self, program_id: &str, progress: &Progress<'_>) -> Result<Value, String> {
+        progress.stage("fetching accounts", None).await?;
+        let counts = self.solana_client.get_account_discriminator_counts(program_id).await?;
+
+        progress.stage("storing discriminators", Some(counts.len() as u64)).await?;
+        for (index, count) in counts.iter().enumerate() {
+            self.db.upload_discriminator(
+                program_id,
+                DiscriminatorKind::Account,
+                count.discriminator.clone(),
+                Vec::new(),
+                ONCHAIN_CONTRIBUTOR,
+                Provenance::new(ProvenanceSource::Chain, ACCOUNT_SCAN_COMPONENT, Some(count.sample_account.clone())),
+            ).await.map_err(|e| e.to_string())?;
+
+            self.db.record_account_count(program_id, &count.discriminator, count.count, &count.spaces)
+                .await
+                .map_err(|e| e.to_string())?;
+
+            progress.processed(index as u64 + 1).await?;
+        }
+
+        Ok(json!({"program_id": program_id, "discriminators": counts.len()}))
+    }
+
+    // Function to ingest the program's transactions that landed after `until`
+    async fn backfill(&self, program_id: &str, until: Option<&str>, progress: &Progress<'_>) -> Result<Value, String> {
+        progress.stage("fetching signatures", None).await?;
+        let signatures = self.solana_client.get_transactions_since(program_id, until).await.map_err(|e| e.to_string())?;
+
+        progress.stage("ingesting transactions", Some(signatures.len() as u64)).await?;
+        let mut newest = until.map(str::to_string);
+        for (index, signature) in signatures.iter().enumerate() {
+            self.solana_client.process_signature(&*self.db, program_id, &signature.signature).await
+                .map_err(|e| e.to_string())?;
+            newest = Some(signature.signature.clone());
+            progress.processed(index as u64 + 1).await?;
+        }
+
+        Ok(json!({
+            "program_id": program_id,
+            "transactions": signatures.len(),
+            // Passed as `until` to pick up where this backfill stopped
+            "newest_signature": newest,
+        }))
+    }
+
+    // Function to analyze the deployed binary of a program and store the candidates it contains
+    async fn analyze_bytecode(&self, program_id: &str, progress: &Progress<'_>) -> Result<Value, String> {
+        progress.stage("fetching program binary", None).await?;
+        let elf = self.solana_client.get_program_binary(program_id).await?;
+
+        let candidates = analyze_elf(&elf).map_err(|e| e.to_string())?;
+        // Provenance references the exact binary the candidates were read from
+        let binary_hash = hex::encode(Sha256::digest(&elf));
+
+        progress.stage("storing candidates", Some(candidates.len() as u64)).await?;
+        let mut results = Vec::new();
+        for (index, candidate) in candidates.iter().enumerate() {
+            let result = store_candidate(&self.db, &self.recovery, program_id, candidate, &binary_hash, BYTECODE_CONTRIBUTOR)
+                .await
+                .map_err(|e| e.to_string())?;
+            results.push(result);
+            progress.processed(index as u64 + 1).await?;
+        }
+
+        Ok(json!({
+            "program_id": program_id,
+            "source": "chain",
+            "provenance": BYTECODE_CONTRIBUTOR,
+            "binary_hash": binary_hash,
+            "candidates": results,
+        }))
+    }
+
+    // Function to import the IDL a program published on-chain
+    async fn fetch_idl(&self, program_id: &str, progress: &Progress<'_>) -> Result<Value, String> {
+        progress.stage("fetching IDL", None).await?;
+        let Some(idl) = fetch_onchain_idl(&self.solana_client, program_id).await? else {
+            // Not an error: most programs never publish an IDL
+            progress.stage("no IDL published", Some(0)).await?;
+            return Ok(json!({"program_id": program_id, "found": false}));
+        };
+
+        progress.stage("storing entries", Some(idl.entries.len() as u64)).await?;
+        for (index, entry) in idl.entries.iter().enumerate() {
+            store_idl_entry(&self.db, program_id, entry, &idl.hash).await.map_err(|e| e.to_string())?;
+            // IDL names often recur in other programs, so they also help name unknown entries elsewhere
+            self.recovery.add_identifier(&entry.name, "idl");
+            progress.processed(index as u64 + 1).await?;
+        }
+
+        Ok(json!({
+            "program_id": program_id,
+            "found": true,
+            "idl_address": idl.address,
+            "idl_hash": idl.hash,
+            "entries": idl.entries.len(),
+        }))
+    }
+
+    // Function to write the audit events matching a filter to a newline delimited JSON file, oldest first
+    async fn export_audit_events(&self, id: &str, mut filter: AuditFilter, progress: &Progress<'_>) -> Result<Value, String> {
+        progress.stage("writing events", None).await?;
+        tokio::fs::create_dir_all(AUDIT_EXPORT_DIR).await.map_err(|e| e.to_string())?;
+        // A retry starts the file over
+        let mut file = tokio::fs::File::create(audit_export_path(id)).await.map_err(|e| e.to_string())?;
+
+        let mut exported = 0u64;
+        loop {
+            let events = self.db.list_audit_events(&filter, true, AUDIT_EXPORT_BATCH_SIZE)
+                .await
+                .map_err(|e| e.to_string())?;
+
+            for event in &events {
+                let mut line = serde_json::to_vec(event).map_err(|e| e.to_string())?;
+                line.push(b'\n');
+                file.write_all(&line).await.map_err(|e| e.to_string())?;
+            }
+            exported += events.len() as u64;
+            progress.processed(exported).await?;
+
+            if events.len() < AUDIT_EXPORT_BATCH_SIZE {
+                break;
+            }
+            filter.cursor = events.last().map(|event| event._key.clone());
+        }
+        file.flush().await.map_err(|e| e.to_string())?;
+
+        Ok(json!({"events": exported, "download_url": format!("/audit/exports/{}", id)}))
+    }
+}
+
+// Function to build the `202 Accepted` response pointing a client at a job to poll
+pub fn job_accepted(job: &Job, started: bool) -> HttpResponse {
+    let status_url = format!("/jobs/{}", job._key);
+    HttpResponse::Accepted()
+        .insert_header(("Location", status_url.clone()))
+        .json(json!({
+            "job_id": job._key,
+            "status_url": status_url,
+            // False when the request joined a job another request had already queued
+            "started": started,
+            "job": job,
+        }))
+}
+
+// Shows the state and progress of a job
+pub async fn job_status_endpoint(db: web::Data<GraphDatabase>, id: web::Path<String>) -> impl Responder {
+    match db.get_job(&id).await {
+        Ok(Some(job)) => HttpResponse::Ok().json(job),
+        Ok(None) => HttpResponse::NotFound().json(json!({"error": "Job not found or expired"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+// Cancels a queued or running job. Users can cancel the jobs they queued; admins can cancel any job.
+pub async fn cancel_job_endpoint(
+    db: web::Data<GraphDatabase>,
+    jobs: web::Data<JobRunner>,
+    user: AuthenticatedUser,
+    id: web::Path<String>,
+) -> impl Responder {
+    let job = match db.get_job(&id).await {
+        Ok(Some(job)) => job,
+        Ok(None) => return HttpResponse::NotFound().json(json!({"error": "Job not found or expired"})),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    };
+    if job.requested_by.as_deref() != Some(user.user_id.as_str()) {
+        if let Err(denied) = user.require(Role::Admin) {
+            return denied.into();
+        }
+    }
+
+    match jobs.cancel(&id).await {
+        Ok(Some(job)) => HttpResponse::Ok().json(job),
+        Ok(None) => HttpResponse::Conflict().json(json!({"error": "Job has already finished"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+// Filters of the admin job list: `?state=dead` lists the dead-letter jobs
+#[derive(Debug, Deserialize)]
+pub struct JobListQuery {
+    state: Option<JobState>,
+    kind: Option<JobKind>,
+    limit: Option<usize>,
+}
+
+// Lists jobs, newest first
+pub async fn list_jobs_endpoint(
+    db: web::Data<GraphDatabase>,
+    user: AuthenticatedUser,
+    query: web::Query<JobListQuery>,
+) -> impl Responder {
+    if let Err(denied) = user.require(Role::Admin) {
+        return denied.into();
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_JOB_LIST_SIZE).clamp(1, MAX_JOB_LIST_SIZE);
+
+    match db.list_jobs(query.state, query.kind, limit).await {
+        Ok(jobs) => HttpResponse::Ok().json(json!({"jobs": jobs})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+// Queues any kind of job, e.g. `{"type": "backfill", "program_id": "..."}`
+pub async fn enqueue_job_endpoint(
+    jobs: web::Data<JobRunner>,
+    user: AuthenticatedUser,
+    spec: web::Json<JobSpec>,
+) -> impl Responder {
+    if let Err(denied) = user.require(Role::Admin) {
+        return denied.into();
+    }
+    let spec = spec.into_inner();
+    if spec.program_id().is_some_and(|program_id| !is_valid_pubkey(program_id)) {
+        return HttpResponse::BadRequest().json(json!({"error": "Invalid program id"}));
+    }
+
+    match jobs.enqueue(spec, Some(user.user_id)).await {
+        Ok((job, added)) => job_accepted(&job, added),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+// Puts a dead or cancelled job back in the queue
+pub async fn requeue_job_endpoint(
+    jobs: web::Data<JobRunner>,
+    user: AuthenticatedUser,
+    id: web::Path<String>,
+) -> impl Responder {
+    if let Err(denied) = user.require(Role::Admin) {
+        return denied.into();
+    }
+
+    match jobs.requeue(&id).await {
+        Ok(Some(job)) => job_accepted(&job, true),
+        Ok(None) => HttpResponse::Conflict().json(json!({
+            "error": "Only dead or cancelled jobs without an equivalent job in the queue can be requeued"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
@@ -0,0 +1,247 @@
+use actix_web::{web, HttpResponse, Responder};
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+use crate::auth::AuthenticatedUser;
+use crate::graph_disc::{unix_timestamp, GraphDatabase, Role};
+use crate::solana_connection::{ListenerMode, SolanaConnection};
+use crate::validation::{is_valid_pubkey, ProgramId};
+
+// Delay before the first restart of a crashed listener, doubled on every consecutive crash
+const INITIAL_RESTART_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(300);
+// A listener that stayed up this long is considered healthy again and its backoff is reset
+const HEALTHY_RUN_DURATION: Duration = Duration::from_secs(120);
+// Upper bound on listeners running at once, since every listener draws on the shared RPC budget
+const MAX_LISTENERS: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ListenerState {
+    Running,
+    Restarting,
+    Stopped,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ListenerStatus {
+    program_id: String,
+    mode: ListenerMode,
+    state: ListenerState,
+    restarts: u32,
+    last_error: Option<String>,
+    // When the current run started, reset on every restart
+    started_at: u64,
+}
+
+// Result of asking the supervisor to start a listener
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartOutcome {
+    Started,
+    AlreadyRunning,
+    // `MAX_LISTENERS` listeners are running already
+    AtCapacity,
+}
+
+struct ListenerHandle {
+    status: Arc<Mutex<ListenerStatus>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl ListenerHandle {
+    fn is_running(&self) -> bool {
+        self.task.as_ref().is_some_and(|task| !task.is_finished())
+    }
+}
+
+// Owns one listener task per watched program and restarts crashed listeners with backoff
+pub struct ListenerSupervisor {
+    db: Arc<GraphDatabase>,
+    solana_client: Arc<SolanaConnection>,
+    default_mode: ListenerMode,
+    listeners: Mutex<HashMap<String, ListenerHandle>>,
+}
+
+impl ListenerSupervisor {
+    pub fn new(db: Arc<GraphDatabase>, solana_client: Arc<SolanaConnection>, default_mode: ListenerMode) -> Self {
+        ListenerSupervisor {
+            db,
+            solana_client,
+            default_mode,
+            listeners: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Function to start a listener for a program, unless one is already running or the cap is reached
+    pub fn start(&self, program_id: &str, mode: Option<ListenerMode>) -> StartOutcome {
+        let mut listeners = self.listeners.lock().unwrap();
+        if listeners.get(program_id).is_some_and(ListenerHandle::is_running) {
+            return StartOutcome::AlreadyRunning;
+        }
+        if listeners.values().filter(|handle| handle.is_running()).count() >= MAX_LISTENERS {
+            return StartOutcome::AtCapacity;
+        }
+
+        let mode = mode.unwrap_or(self.default_mode);
+        let status = Arc::new(Mutex::new(ListenerStatus {
+            program_id: program_id.to_string(),
+            mode,
+            state: ListenerState::Running,
+            restarts: 0,
+            last_error: None,
+            started_at: unix_timestamp(),
+        }));
+
+        let task = tokio::spawn(Self::supervise(
+            self.db.clone(),
+            self.solana_client.clone(),
+            program_id.to_string(),
+            mode,
+            status.clone(),
+        ));
+
+        println!("Started {:?} listener for {}", mode, program_id);
+        listeners.insert(program_id.to_string(), ListenerHandle { status, task: Some(task) });
+        StartOutcome::Started
+    }
+
+    // Function to start a listener only if the program has never been watched before
+    pub fn start_if_unknown(&self, program_id: &str) {
+        let known = self.listeners.lock().unwrap().contains_key(program_id);
+        if !known && self.start(program_id, None) == StartOutcome::AtCapacity {
+            eprintln!("Not watching {}: {} listeners are running already", program_id, MAX_LISTENERS);
+        }
+    }
+
+    // Function to stop a program's listener. Returns false if the program is not supervised.
+    pub fn stop(&self, program_id: &str) -> bool {
+        let mut listeners = self.listeners.lock().unwrap();
+        let Some(handle) = listeners.get_mut(program_id) else {
+            return false;
+        };
+
+        if let Some(task) = handle.task.take() {
+            task.abort();
+        }
+        handle.status.lock().unwrap().state = ListenerState::Stopped;
+        println!("Stopped listener for {}", program_id);
+        true
+    }
+
+    // Function to get the status of every supervised listener
+    pub fn statuses(&self) -> Vec<ListenerStatus> {
+        let listeners = self.listeners.lock().unwrap();
+        let mut statuses: Vec<ListenerStatus> = listeners
+            .values()
+            .map(|handle| handle.status.lock().unwrap().clone())
+            .collect();
+        statuses.sort_by(|a, b| a.program_id.cmp(&b.program_id));
+        statuses
+    }
+
+    // Runs the listener in a loop, catching errors and panics, and restarting it with backoff
+    async fn supervise(
+        db: Arc<GraphDatabase>,
+        solana_client: Arc<SolanaConnection>,
+        program_id: String,
+        mode: ListenerMode,
+        status: Arc<Mutex<ListenerStatus>>,
+    ) {
+        let mut backoff = INITIAL_RESTART_BACKOFF;
+
+        loop {
+            {
+                let mut status = status.lock().unwrap();
+                status.state = ListenerState::Running;
+                status.started_at = unix_timestamp();
+            }
+            let started = Instant::now();
+
+            let outcome = AssertUnwindSafe(solana_client.run_listener(mode, db.clone(), program_id.clone()))
+                .catch_unwind()
+                .await;
+
+            let error = match outcome {
+                Ok(Ok(())) => "listener exited".to_string(),
+                Ok(Err(e)) => e.to_string(),
+                Err(_) => "listener panicked".to_string(),
+            };
+            eprintln!("Listener for {} stopped: {}, restarting in {:?}", program_id, error, backoff);
+
+            if started.elapsed() >= HEALTHY_RUN_DURATION {
+                backoff = INITIAL_RESTART_BACKOFF;
+            }
+
+            {
+                let mut status = status.lock().unwrap();
+                status.state = ListenerState::Restarting;
+                status.restarts += 1;
+                status.last_error = Some(error);
+            }
+
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_RESTART_BACKOFF);
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StartListenerRequest {
+    program_id: String,
+    mode: Option<ListenerMode>,
+}
+
+pub async fn list_listeners_endpoint(supervisor: web::Data<ListenerSupervisor>) -> impl Responder {
+    HttpResponse::Ok().json(supervisor.statuses())
+}
+
+pub async fn start_listener_endpoint(
+    supervisor: web::Data<ListenerSupervisor>,
+    user: AuthenticatedUser,
+    request: web::Json<StartListenerRequest>,
+) -> impl Responder {
+    if let Err(denied) = user.require(Role::Admin) {
+        return denied.into();
+    }
+    let StartListenerRequest { program_id, mode } = request.into_inner();
+
+    if !is_valid_pubkey(&program_id) {
+        return HttpResponse::BadRequest().json(json!({"error": "Invalid program_id"}));
+    }
+
+    match supervisor.start(&program_id, mode) {
+        StartOutcome::Started => {
+            HttpResponse::Created().json(json!({"status": "Listener started", "program_id": program_id}))
+        }
+        StartOutcome::AlreadyRunning => {
+            HttpResponse::Conflict().json(json!({"error": "Listener already running", "program_id": program_id}))
+        }
+        StartOutcome::AtCapacity => HttpResponse::TooManyRequests().json(json!({
+            "error": "Too many listeners running",
+            "max_listeners": MAX_LISTENERS,
+        })),
+    }
+}
+
+pub async fn stop_listener_endpoint(
+    supervisor: web::Data<ListenerSupervisor>,
+    user: AuthenticatedUser,
+    program_id: ProgramId,
+) -> impl Responder {
+    if let Err(denied) = user.require(Role::Admin) {
+        return denied.into();
+    }
+    let program_id = program_id.into_inner();
+
+    if supervisor.stop(&program_id) {
+        HttpResponse::Ok().json(json!({"status": "Listener stopped", "program_id": program_id}))
+    } else {
+        HttpResponse::NotFound().json(json!({"error": "No listener for program", "program_id": program_id}))
+    }
+}
@@ -0,0 +1,174 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+
+use crate::ingest::ExecutedInstruction;
+
+// One program invocation reconstructed from a transaction's log messages
+#[derive(Debug, Clone)]
+pub struct Invocation {
+    pub program_id: String,
+    // Name from an Anchor `Program log: Instruction: <Name>` line
+    pub instruction_name: Option<String>,
+    // Payloads from `Program data: <base64>` lines, e.g. Anchor `emit!` events
+    pub data: Vec<Vec<u8>>,
+}
+
+// Function to rebuild the invocations of a transaction from its logs, in execution order.
+// The invoke-depth stack attributes each log line to the program that is currently executing.
+pub fn parse_invocations(logs: &[String]) -> Vec<Invocation> {
+    let mut invocations: Vec<Invocation> = Vec::new();
+    let mut stack: Vec<usize> = Vec::new();
+
+    for line in logs {
+        if line.starts_with("Log truncated") {
+            break;
+        }
+
+        if let Some(program_id) = parse_invoke(line) {
+            invocations.push(Invocation {
+                program_id: program_id.to_string(),
+                instruction_name: None,
+                data: Vec::new(),
+            });
+            stack.push(invocations.len() - 1);
+        } else if is_invocation_end(line) {
+            stack.pop();
+        } else if let Some(name) = line.strip_prefix("Program log: Instruction: ") {
+            if let Some(invocation) = stack.last().map(|index| &mut invocations[*index]) {
+                // Only the first line counts; later ones are free-form program output
+                if invocation.instruction_name.is_none() {
+                    invocation.instruction_name = Some(name.trim().to_string());
+                }
+            }
+        } else if let Some(encoded) = line.strip_prefix("Program data: ") {
+            // A line may carry several space separated chunks; Anchor events use exactly one
+            let Some(invocation) = stack.last().map(|index| &mut invocations[*index]) else {
+                continue;
+            };
+            if let Some(chunk) = encoded.split_whitespace().next() {
+                if let Ok(payload) = BASE64.decode(chunk) {
+                    invocation.data.push(payload);
+                }
+            }
+        }
+    }
+
+    invocations
+}
+
+// Function to pair every executed instruction with the invocation that ran it.
+// Instructions that never log an invocation (precompiles, or anything after a failure) get `None`.
+pub fn align_invocations<'a>(
+    instructions: &[ExecutedInstruction],
+    invocations: &'a [Invocation],
+) -> Vec<Option<&'a Invocation>> {
+    let mut next = 0;
+
+    instructions
+        .iter()
+        .map(|instruction| match invocations.get(next) {
+            Some(invocation) if invocation.program_id == instruction.program_id => {
+                next += 1;
+                Some(invocation)
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+// Parses "Program <id> invoke [<depth>]" into the program id
+fn parse_invoke(line: &str) -> Option<&str> {
+    let rest = line.strip_prefix("Program ")?;
+    let (program_id, depth) = rest.split_once(" invoke [")?;
+    if !is_program_id(program_id) {
+        return None;
+    }
+    depth.strip_suffix(']')?.parse::<usize>().ok()?;
+    Some(program_id)
+}
+
+// Matches "Program <id> success" and "Program <id> failed: <reason>"
+fn is_invocation_end(line: &str) -> bool {
+    match line.strip_prefix("Program ").and_then(|rest| rest.split_once(' ')) {
+        Some((program_id, outcome)) => {
+            is_program_id(program_id) && (outcome == "success" || outcome.starts_with("failed"))
+        }
+        None => false,
+    }
+}
+
+// Distinguishes "Program <base58 id> ..." from "Program log: ..." style lines
+fn is_program_id(token: &str) -> bool {
+    !token.is_empty() && token.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AMM: &str = "675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8";
+    const TOKEN: &str = "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA";
+
+    fn logs(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    fn executed(index: usize, inner_index: Option<usize>, program_id: &str) -> ExecutedInstruction {
+        ExecutedInstruction {
+            index,
+            inner_index,
+            program_id: program_id.to_string(),
+            accounts: Vec::new(),
+            data: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn attributes_names_and_data_to_the_executing_program() {
+        let event = BASE64.encode([1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        let invocations = parse_invocations(&logs(&[
+            &format!("Program {} invoke [1]", AMM),
+            "Program log: Instruction: Swap",
+            &format!("Program {} invoke [2]", TOKEN),
+            "Program log: Instruction: Transfer",
+            &format!("Program {} consumed 4645 of 180000 compute units", TOKEN),
+            &format!("Program {} success", TOKEN),
+            "Program log: Instruction: NotAName",
+            &format!("Program data: {}", event),
+            &format!("Program {} success", AMM),
+        ]));
+
+        assert_eq!(invocations.len(), 2);
+        assert_eq!(invocations[0].program_id, AMM);
+        assert_eq!(invocations[0].instruction_name.as_deref(), Some("Swap"));
+        assert_eq!(invocations[0].data, vec![vec![1, 2, 3, 4, 5, 6, 7, 8, 9]]);
+        assert_eq!(invocations[1].program_id, TOKEN);
+        assert_eq!(invocations[1].instruction_name.as_deref(), Some("Transfer"));
+        assert!(invocations[1].data.is_empty());
+    }
+
+    #[test]
+    fn stops_at_truncated_logs() {
+        let invocations = parse_invocations(&logs(&[
+            &format!("Program {} invoke [1]", AMM),
+            "Log truncated",
+            &format!("Program {} invoke [2]", TOKEN),
+        ]));
+
+        assert_eq!(invocations.len(), 1);
+        assert_eq!(invocations[0].program_id, AMM);
+    }
+
+    #[test]
+    fn skips_instructions_that_did_not_log_an_invocation() {
+        let invocations = parse_invocations(&logs(&[
+            &format!("Program {} invoke [1]", AMM),
+            &format!("Program {} success", AMM),
+        ]));
+        let instructions = [executed(0, None, TOKEN), executed(1, None, AMM)];
+
+        let aligned = align_invocations(&instructions, &invocations);
+        assert!(aligned[0].is_none());
+        assert_eq!(aligned[1].map(|invocation| invocation.program_id.as_str()), Some(AMM));
+    }
+}
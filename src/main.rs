@@ -1,19 +1,73 @@
-use actix_web::{web, App, HttpServer};
+use actix_web::{middleware, web, App, HttpServer};
 use std::sync::Arc;
+use std::time::Duration;
 use actix_cors::Cors;
 
 // Importing modules containing functionalities
+mod anchor;
+mod api_keys;
+mod audit;
+mod auth;
+mod authority;
+mod bytecode;
+mod change_stream;
+mod decode;
 mod graph_disc;
+mod idl;
+mod ingest;
+mod jobs;
+mod listener_supervisor;
+mod log_parser;
+mod moderation;
+mod name_recovery;
 mod query;
+mod rate_limit;
+mod reputation;
 mod solana_connection;
+mod validation;
 
 // Importing specific functionalities from the modules
-use graph_disc::GraphDatabase;
-use query::{query_discriminators_endpoint,  upload_discriminator_endpoint };
-use solana_connection::SolanaConnection;
+use api_keys::{
+    create_api_key_endpoint, list_api_keys_endpoint, rate_limit_middleware, revoke_api_key_endpoint, ApiKeyAuthenticator,
+};
+use audit::{
+    create_audit_export_endpoint, download_audit_export_endpoint, export_audit_events_endpoint, list_audit_events_endpoint,
+    request_id_middleware,
+};
+use auth::{issue_nonce_endpoint, logout_endpoint, verify_signature_endpoint, SignInChallenges};
+use authority::{
+    claim_challenge_endpoint, claim_program_endpoint, get_program_claim_endpoint, publish_official_endpoint,
+};
+use bytecode::analyze_program_endpoint;
+use change_stream::{discriminator_events_endpoint, discriminator_socket_endpoint};
+use decode::{
+    decode_account_endpoint, decode_accounts_endpoint, decode_instruction_endpoint, decode_transaction_endpoint,
+};
+use graph_disc::{GraphDatabase, Role};
+use idl::fetch_idl_endpoint;
+use jobs::{
+    cancel_job_endpoint, enqueue_job_endpoint, job_status_endpoint, list_jobs_endpoint, requeue_job_endpoint, JobRunner,
+};
+use listener_supervisor::{list_listeners_endpoint, start_listener_endpoint, stop_listener_endpoint, ListenerSupervisor};
+use rate_limit::{rpc_metrics_endpoint, RequestLimiter};
+use reputation::{ranked_names_endpoint, user_profile_endpoint};
+use moderation::{
+    approve_submission_endpoint, list_submissions_endpoint, my_submissions_endpoint, reject_submission_endpoint,
+    retract_submission_endpoint, set_user_role_endpoint,
+};
+use name_recovery::{discriminator_candidates_endpoint, NameRecovery};
+use query::{count_accounts_endpoint, query_discriminators_endpoint, query_events_endpoint,  upload_discriminator_endpoint };
+use solana_connection::{ListenerMode, SolanaConnection};
+use validation::{json_config, query_config, MAX_PAYLOAD_SIZE};
+
+// RPC endpoint used when SOLANA_RPC_URLS is not set
+const DEFAULT_RPC_URL: &str = "https://api.devnet.solana.com";
+// Largest program binary accepted by the bytecode analyzer
+const MAX_PROGRAM_BINARY_SIZE: usize = 10 * 1024 * 1024;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
     println!("Hello World!");
 
     // Try to create a database connection
@@ -28,7 +82,16 @@ async fn main() -> std::io::Result<()> {
     println!("Successfully connected to the database.");
 
 
-    let solana_client = Arc::new(SolanaConnection::new("https://api.devnet.solana.com"));
+    // RPC endpoints in order of preference, from SOLANA_RPC_URLS (comma separated); calls fail over to the
+    // next one when an endpoint is unhealthy
+    let rpc_urls = std::env::var("SOLANA_RPC_URLS").unwrap_or_else(|_| DEFAULT_RPC_URL.to_string());
+    let rpc_urls: Vec<&str> = rpc_urls.split(',').map(str::trim).filter(|url| !url.is_empty()).collect();
+    if rpc_urls.is_empty() {
+        eprintln!("SOLANA_RPC_URLS does not list any RPC endpoint");
+        std::process::exit(1);
+    }
+    let solana_client = Arc::new(SolanaConnection::new(&rpc_urls, 10.0));
+    solana_client.spawn_health_checks(Duration::from_secs(30));
 
     // Fetch the list of program IDs from the database
     let program_ids = match db.get_all_program_ids().await {
@@ -41,29 +104,51 @@ async fn main() -> std::io::Result<()> {
 
     println!("Fetched {} program IDs", program_ids.len());
 
-    // for program_id in program_ids {
-    //     let db_clone = db.clone();
-    //     let solana_client_clone = solana_client.clone();
-    //     let program_id_clone = program_id.clone();
-    
-
-    //     tokio::spawn(async move {
-    //         if let Err(e) = solana_client_clone.real_time_listener(db_clone, program_id_clone).await {
-    //             eprintln!("Error in real time listener: {}", e);
-    //         }
-    //     });
-        
-
-    // }
-    
-    
-    
+    // Start a supervised listener for every program already in the directory
+    let supervisor = Arc::new(ListenerSupervisor::new(db.clone(), solana_client.clone(), ListenerMode::WebSocket));
+    for program_id in program_ids {
+        supervisor.start_if_unknown(&program_id);
+    }
+
+    // Precompute the name lookup table and keep it in sync with names added to the directory
+    let name_recovery = Arc::new(NameRecovery::new());
+    name_recovery.spawn_refresh(db.clone(), Duration::from_secs(600));
+
+    // Slow work runs from the persistent job queue while clients poll its job
+    let jobs = Arc::new(JobRunner::new(db.clone(), solana_client.clone(), name_recovery.clone()));
+    jobs.spawn();
+
+    // Wallets listed in ADMIN_WALLETS (comma separated) are made admins, so roles can be handed out
+    for wallet in std::env::var("ADMIN_WALLETS").unwrap_or_default().split(',').map(str::trim).filter(|w| !w.is_empty()) {
+        if let Err(e) = db.set_user_role(wallet, Role::Admin, "ADMIN_WALLETS").await {
+            eprintln!("Failed to make {} an admin: {}", wallet, e);
+        }
+    }
+
+    // Sign-in challenges are short lived and shared by every worker
+    let challenges = web::Data::new(SignInChallenges::new());
+    // Request rate limits are enforced per process
+    let limiter = web::Data::new(RequestLimiter::new());
+    let authenticator = web::Data::new(ApiKeyAuthenticator::new(db.clone()));
+
     println!("Starting HTTP server on 127.0.0.1:8080");
 
     HttpServer::new( move || {
         App::new()
             .app_data(web::Data::from(db.clone()))
             .app_data(web::Data::from(solana_client.clone()))
+            .app_data(web::Data::from(supervisor.clone()))
+            .app_data(web::Data::from(name_recovery.clone()))
+            .app_data(web::Data::from(jobs.clone()))
+            .app_data(challenges.clone())
+            .app_data(limiter.clone())
+            .app_data(authenticator.clone())
+            // Bound every request body so oversized input is rejected before it reaches a handler
+            .app_data(json_config())
+            .app_data(query_config())
+            .app_data(web::PayloadConfig::new(MAX_PAYLOAD_SIZE))
+            .wrap(middleware::from_fn(rate_limit_middleware))
+            .wrap(middleware::from_fn(request_id_middleware))
             .wrap(Cors::default()
                 .allow_any_origin()
                 .allow_any_method()
@@ -72,8 +157,55 @@ async fn main() -> std::io::Result<()> {
             .service(
                 web::scope("")
                     .route("/", web::get().to(|| async { "Hello World!" }))
+                    .route("/auth/nonce", web::post().to(issue_nonce_endpoint))
+                    .route("/auth/verify", web::post().to(verify_signature_endpoint))
+                    .route("/auth/logout", web::post().to(logout_endpoint))
+                    .route("/api_keys", web::get().to(list_api_keys_endpoint))
+                    .route("/api_keys", web::post().to(create_api_key_endpoint))
+                    .route("/api_keys/{id}", web::delete().to(revoke_api_key_endpoint))
+                    .route("/users/{id}", web::get().to(user_profile_endpoint))
+                    .route("/jobs/{id}", web::get().to(job_status_endpoint))
+                    .route("/jobs/{id}/cancel", web::post().to(cancel_job_endpoint))
+                    .route("/admin/jobs", web::get().to(list_jobs_endpoint))
+                    .route("/admin/jobs", web::post().to(enqueue_job_endpoint))
+                    .route("/admin/jobs/{id}/requeue", web::post().to(requeue_job_endpoint))
+                    .route("/audit", web::get().to(list_audit_events_endpoint))
+                    .route("/audit/export", web::get().to(export_audit_events_endpoint))
+                    .route("/audit/exports", web::post().to(create_audit_export_endpoint))
+                    .route("/audit/exports/{id}", web::get().to(download_audit_export_endpoint))
+                    .route("/users/{id}/role", web::put().to(set_user_role_endpoint))
+                    .route("/programs/{program_id}/claim", web::get().to(get_program_claim_endpoint))
+                    .route("/programs/{program_id}/claim", web::post().to(claim_program_endpoint))
+                    .route("/programs/{program_id}/claim/challenge", web::post().to(claim_challenge_endpoint))
+                    .route("/programs/{program_id}/official", web::post().to(publish_official_endpoint))
+                    .route("/programs/{program_id}/idl/fetch", web::post().to(fetch_idl_endpoint))
+                    .route("/submissions", web::get().to(my_submissions_endpoint))
+                    .route("/submissions/{id}", web::delete().to(retract_submission_endpoint))
+                    .route("/moderation/submissions", web::get().to(list_submissions_endpoint))
+                    .route("/moderation/submissions/{id}/approve", web::post().to(approve_submission_endpoint))
+                    .route("/moderation/submissions/{id}/reject", web::post().to(reject_submission_endpoint))
                     .route("/upload_discriminator/{program_id}", web::post().to(upload_discriminator_endpoint))
                     .route("/query_discriminators/{program_id}", web::get().to(query_discriminators_endpoint))
+                    .route("/query_discriminators/{program_id}/accounts/{discriminator}", web::get().to(count_accounts_endpoint))
+                    .route("/discriminators/{discriminator}/candidates", web::get().to(discriminator_candidates_endpoint))
+                    .route("/discriminators/{program_id}/{discriminator}/names", web::get().to(ranked_names_endpoint))
+                    .service(
+                        // Program binaries uploaded for analysis can be several megabytes
+                        web::resource("/analyze/{program_id}")
+                            .app_data(web::PayloadConfig::new(MAX_PROGRAM_BINARY_SIZE))
+                            .route(web::post().to(analyze_program_endpoint))
+                    )
+                    .route("/decode", web::post().to(decode_instruction_endpoint))
+                    .route("/decode/transaction", web::post().to(decode_transaction_endpoint))
+                    .route("/decode/account/{address}", web::get().to(decode_account_endpoint))
+                    .route("/decode/accounts", web::post().to(decode_accounts_endpoint))
+                    .route("/events/{program_id}", web::get().to(query_events_endpoint))
+                    .route("/stream/discriminators", web::get().to(discriminator_events_endpoint))
+                    .route("/ws/discriminators", web::get().to(discriminator_socket_endpoint))
+                    .route("/metrics/rpc", web::get().to(rpc_metrics_endpoint))
+                    .route("/listeners", web::get().to(list_listeners_endpoint))
+                    .route("/listeners", web::post().to(start_listener_endpoint))
+                    .route("/listeners/{program_id}", web::delete().to(stop_listener_endpoint))
             )
     })
     .bind("127.0.0.1:8080")?
@@ -0,0 +1,221 @@
+use actix_web::{web, HttpResponse, Responder};
+use log::error;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::anchor::discriminator_for;
+use crate::auth::AuthenticatedUser;
+use crate::graph_disc::{
+    DatabaseError, DiscriminatorName, GraphDatabase, Provenance, ProvenanceSource, Role, Submission, SubmissionStatus,
+    BYTECODE_CONTRIBUTOR,
+};
+use crate::listener_supervisor::ListenerSupervisor;
+use crate::validation::is_valid_pubkey;
+
+// Approved submissions after which a contributor's uploads skip the moderation queue
+pub const TRUSTED_CONTRIBUTOR_APPROVALS: u64 = 5;
+// Reviewer recorded on submissions that were published without review
+pub const AUTO_APPROVER: &str = "auto";
+const SUBMISSION_PAGE_SIZE: usize = 100;
+// Component recorded in the provenance of published submissions
+const SUBMISSION_COMPONENT: &str = "submissions";
+
+// Function to decide whether a user's submissions are published without review
+pub async fn is_trusted(db: &GraphDatabase, user: &AuthenticatedUser) -> Result<bool, DatabaseError> {
+    if user.role >= Role::Moderator {
+        return Ok(true);
+    }
+    Ok(db.count_approved_submissions(&user.user_id).await? >= TRUSTED_CONTRIBUTOR_APPROVALS)
+}
+
+// Function to publish an approved submission to the directory.
+// Returns whether the submitted name hashes to the discriminator, when a name was submitted.
+pub async fn publish_submission(
+    db: &GraphDatabase,
+    supervisor: &ListenerSupervisor,
+    submission: &Submission,
+) -> Result<Option<bool>, DatabaseError> {
+    let Submission { program_id, kind, discriminator_data, details, .. } = submission;
+
+    // Mappings the contributor says were taken from an IDL are recorded as such
+    let source = match details.source.as_deref() {
+        Some("idl") => ProvenanceSource::Idl,
+        Some(BYTECODE_CONTRIBUTOR) => ProvenanceSource::Bytecode,
+        _ => ProvenanceSource::Manual,
+    };
+    let provenance = Provenance::new(source, SUBMISSION_COMPONENT, Some(submission._key.clone()));
+
+    db.upload_discriminator(program_id, *kind, discriminator_data.clone(), Vec::new(), &submission.user_id, provenance.clone()).await?;
+    db.update_discriminator_details(program_id, *kind, discriminator_data, details, &submission.user_id).await?;
+
+    // A submitted name is verified when it hashes to the submitted bytes
+    let verified = match &submission.name {
+        Some(name) => {
+            let verified = discriminator_for(*kind, name)[..] == discriminator_data[..];
+            let name = DiscriminatorName {
+                name: name.clone(),
+                source: details.source.clone().unwrap_or_else(|| "manual".to_string()),
+                verified,
+            };
+            db.name_discriminator(program_id, *kind, discriminator_data, name, provenance, &submission.user_id).await?;
+            Some(verified)
+        }
+        None => None,
+    };
+
+    // Start watching programs the first time they are uploaded
+    supervisor.start_if_unknown(program_id);
+    Ok(verified)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SubmissionFilter {
+    status: Option<SubmissionStatus>,
+    user_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReviewRequest {
+    note: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetRoleRequest {
+    role: Role,
+}
+
+// Lists the moderation queue; pending submissions unless another status is asked for
+pub async fn list_submissions_endpoint(
+    db: web::Data<GraphDatabase>,
+    user: AuthenticatedUser,
+    filter: web::Query<SubmissionFilter>,
+) -> impl Responder {
+    if let Err(denied) = user.require(Role::Moderator) {
+        return denied.into();
+    }
+
+    let status = filter.status.unwrap_or(SubmissionStatus::Pending);
+    match db.list_submissions(Some(status), filter.user_id.as_deref(), SUBMISSION_PAGE_SIZE).await {
+        Ok(submissions) => HttpResponse::Ok().json(submissions),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+// Lists the signed-in user's own submissions and their review state
+pub async fn my_submissions_endpoint(db: web::Data<GraphDatabase>, user: AuthenticatedUser) -> impl Responder {
+    match db.list_submissions(None, Some(&user.user_id), SUBMISSION_PAGE_SIZE).await {
+        Ok(submissions) => HttpResponse::Ok().json(submissions),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+// Withdraws one of the signed-in user's own submissions while it is still pending
+pub async fn retract_submission_endpoint(
+    db: web::Data<GraphDatabase>,
+    user: AuthenticatedUser,
+    id: web::Path<String>,
+) -> impl Responder {
+    match db.retract_submission(&id, &user.user_id).await {
+        Ok(Some(submission)) => HttpResponse::Ok().json(submission),
+        Ok(None) => HttpResponse::NotFound().json(json!({"error": "No pending submission of yours with this id"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+// Approves a pending submission and publishes it to the directory
+pub async fn approve_submission_endpoint(
+    db: web::Data<GraphDatabase>,
+    supervisor: web::Data<ListenerSupervisor>,
+    user: AuthenticatedUser,
+    id: web::Path<String>,
+    review: Option<web::Json<ReviewRequest>>,
+) -> impl Responder {
+    if let Err(denied) = user.require(Role::Moderator) {
+        return denied.into();
+    }
+    let note = review.and_then(|review| review.into_inner().note);
+
+    // Claimed before publishing, so concurrent reviews or a retraction cannot race the publish
+    let submission = match db
+        .review_submission(&id, SubmissionStatus::Pending, SubmissionStatus::Publishing, &user.user_id, note.as_deref())
+        .await
+    {
+        Ok(Some(submission)) => submission,
+        Ok(None) => match db.get_submission(&id).await {
+            Ok(Some(_)) => return HttpResponse::Conflict().json(json!({"error": "Submission was already reviewed"})),
+            Ok(None) => return HttpResponse::NotFound().json(json!({"error": "Submission not found"})),
+            Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+        },
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    };
+
+    let verified = match publish_submission(&db, &supervisor, &submission).await {
+        Ok(verified) => verified,
+        Err(e) => {
+            error!("Error publishing submission {}: {}", submission._key, e);
+            // Back to the queue, so it can be approved again
+            if let Err(e) = db
+                .review_submission(&id, SubmissionStatus::Publishing, SubmissionStatus::Pending, &user.user_id, None)
+                .await
+            {
+                error!("Error releasing submission {}: {}", submission._key, e);
+            }
+            return HttpResponse::InternalServerError().json(json!({"error": "Failed to publish submission"}));
+        }
+    };
+
+    match db
+        .review_submission(&id, SubmissionStatus::Publishing, SubmissionStatus::Approved, &user.user_id, note.as_deref())
+        .await
+    {
+        Ok(Some(submission)) => HttpResponse::Ok().json(json!({"submission": submission, "name_verified": verified})),
+        Ok(None) => HttpResponse::Conflict().json(json!({"error": "Submission was already reviewed"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+// Rejects a pending submission; a note explaining why is required
+pub async fn reject_submission_endpoint(
+    db: web::Data<GraphDatabase>,
+    user: AuthenticatedUser,
+    id: web::Path<String>,
+    review: web::Json<ReviewRequest>,
+) -> impl Responder {
+    if let Err(denied) = user.require(Role::Moderator) {
+        return denied.into();
+    }
+    let Some(note) = review.into_inner().note.filter(|note| !note.trim().is_empty()) else {
+        return HttpResponse::BadRequest().json(json!({"error": "A note is required when rejecting"}));
+    };
+
+    match db
+        .review_submission(&id, SubmissionStatus::Pending, SubmissionStatus::Rejected, &user.user_id, Some(&note))
+        .await
+    {
+        Ok(Some(submission)) => HttpResponse::Ok().json(submission),
+        Ok(None) => HttpResponse::NotFound().json(json!({"error": "No pending submission with this id"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+// Assigns a role to a user
+pub async fn set_user_role_endpoint(
+    db: web::Data<GraphDatabase>,
+    user: AuthenticatedUser,
+    user_id: web::Path<String>,
+    request: web::Json<SetRoleRequest>,
+) -> impl Responder {
+    if let Err(denied) = user.require(Role::Admin) {
+        return denied.into();
+    }
+    let user_id = user_id.into_inner();
+    if !is_valid_pubkey(&user_id) {
+        return HttpResponse::BadRequest().json(json!({"error": "Invalid user id"}));
+    }
+    let role = request.into_inner().role;
+
+    match db.set_user_role(&user_id, role, &user.user_id).await {
+        Ok(()) => HttpResponse::Ok().json(json!({"user_id": user_id, "role": role})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
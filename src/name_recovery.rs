@@ -0,0 +1,193 @@
+use actix_web::{web, HttpResponse, Responder};
+use serde::Serialize;
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use crate::anchor::{sighash, to_pascal_case, to_snake_case, ACCOUNT_NAMESPACE, EVENT_NAMESPACE, INSTRUCTION_NAMESPACE};
+use crate::graph_disc::{DatabaseError, DiscriminatorKind, DiscriminatorName, GraphDatabase, Provenance, ProvenanceSource};
+use crate::query::KindFilter;
+use crate::validation::DiscriminatorParam;
+
+// Common verbs of instruction handlers, combined with the nouns below ("initialize_pool", "close_position", ...)
+const VERBS: &[&str] = &[
+    "initialize", "init", "create", "update", "set", "close", "open", "deposit", "withdraw", "transfer",
+    "swap", "mint", "burn", "stake", "unstake", "claim", "register", "add", "remove", "delete", "cancel",
+    "place", "settle", "execute", "approve", "revoke", "freeze", "thaw", "lock", "unlock", "borrow",
+    "repay", "liquidate", "harvest", "collect", "distribute", "fund", "refund", "buy", "sell", "redeem",
+    "migrate", "pause", "unpause", "resize", "crank", "consume", "accept", "propose", "vote", "delegate",
+];
+
+const NOUNS: &[&str] = &[
+    "pool", "vault", "config", "state", "global", "user", "account", "position", "market", "order",
+    "authority", "admin", "owner", "fee", "fees", "reward", "rewards", "stake", "escrow", "listing",
+    "auction", "bid", "offer", "trade", "token", "tokens", "mint", "metadata", "collection", "nft",
+    "oracle", "price", "reserve", "obligation", "loan", "liquidity", "lp", "farm", "treasury", "proposal",
+    "vote", "member", "game", "round", "ticket", "whitelist", "counter", "profile", "bond", "ledger",
+];
+
+// Suffixes commonly appended to account and event type names ("PoolState", "SwapEvent", ...)
+const SUFFIXES: &[&str] = &["state", "config", "account", "info", "data", "event", "created", "updated", "closed"];
+
+// Component and actor recorded for names recovered in the background
+const RECOVERY_COMPONENT: &str = "name_recovery";
+
+// A name whose Anchor hash matches a discriminator
+#[derive(Debug, Clone, Serialize)]
+pub struct NameCandidate {
+    pub name: String,
+    pub kind: DiscriminatorKind,
+    // The exact string that was hashed, e.g. "global:initialize_pool"
+    pub preimage: String,
+    // "dictionary" for built-in identifiers, "directory" for names already known for other programs
+    pub source: String,
+}
+
+// Precomputed lookup from 8 byte discriminators to candidate names under the Anchor schemes
+pub struct NameRecovery {
+    table: RwLock<HashMap<[u8; 8], Vec<NameCandidate>>>,
+    identifiers: RwLock<HashSet<String>>,
+}
+
+impl NameRecovery {
+    // Function to build the lookup table from the built-in dictionary
+    pub fn new() -> Self {
+        let recovery = NameRecovery {
+            table: RwLock::new(HashMap::new()),
+            identifiers: RwLock::new(HashSet::new()),
+        };
+
+        for word in VERBS.iter().chain(NOUNS) {
+            recovery.add_identifier(word, "dictionary");
+        }
+        for verb in VERBS {
+            for noun in NOUNS {
+                recovery.add_identifier(&format!("{}_{}", verb, noun), "dictionary");
+            }
+        }
+        for noun in NOUNS.iter().chain(VERBS) {
+            for suffix in SUFFIXES {
+                recovery.add_identifier(&format!("{}_{}", noun, suffix), "dictionary");
+            }
+        }
+
+        recovery
+    }
+
+    // Function to hash an identifier under the instruction, account and event schemes and add it to the table
+    pub fn add_identifier(&self, identifier: &str, source: &str) {
+        let snake = to_snake_case(identifier);
+        if snake.is_empty() || !self.identifiers.write().unwrap().insert(snake.clone()) {
+            return;
+        }
+        let pascal = to_pascal_case(&snake);
+
+        let schemes = [
+            (DiscriminatorKind::Instruction, INSTRUCTION_NAMESPACE, &snake),
+            (DiscriminatorKind::Account, ACCOUNT_NAMESPACE, &pascal),
+            (DiscriminatorKind::Event, EVENT_NAMESPACE, &pascal),
+        ];
+
+        let mut table = self.table.write().unwrap();
+        for (kind, namespace, name) in schemes {
+            table.entry(sighash(namespace, name)).or_default().push(NameCandidate {
+                name: name.clone(),
+                kind,
+                preimage: format!("{}:{}", namespace, name),
+                source: source.to_string(),
+            });
+        }
+    }
+
+    // Function to look up the names whose hash matches a discriminator
+    pub fn candidates(&self, discriminator: &[u8]) -> Vec<NameCandidate> {
+        let Ok(key) = <[u8; 8]>::try_from(discriminator) else {
+            return Vec::new();
+        };
+        self.table.read().unwrap().get(&key).cloned().unwrap_or_default()
+    }
+
+    // Function to add every name already in the directory, so IDL names of one program help name others
+    pub async fn load_directory_names(&self, db: &GraphDatabase) -> Result<(), DatabaseError> {
+        for name in db.get_all_names().await? {
+            self.add_identifier(&name, "directory");
+        }
+        Ok(())
+    }
+
+    // Function to name every unnamed entry that has exactly one candidate of its kind.
+    // Returns the number of entries named.
+    pub async fn recover_names(&self, db: &GraphDatabase) -> Result<usize, DatabaseError> {
+        let mut named = 0;
+
+        for entry in db.query_unnamed_discriminators().await? {
+            let candidates: Vec<NameCandidate> = self
+                .candidates(&entry.discriminator_data)
+                .into_iter()
+                .filter(|candidate| candidate.kind == entry.kind)
+                .collect();
+
+            if let [candidate] = candidates.as_slice() {
+                db.name_discriminator(
+                    &entry.program_id,
+                    entry.kind,
+                    &entry.discriminator_data,
+                    DiscriminatorName {
+                        name: candidate.name.clone(),
+                        source: candidate.source.clone(),
+                        verified: true,
+                    },
+                    Provenance::new(ProvenanceSource::Dictionary, RECOVERY_COMPONENT, Some(candidate.preimage.clone())),
+                    RECOVERY_COMPONENT,
+                ).await?;
+                named += 1;
+            }
+        }
+
+        Ok(named)
+    }
+
+    // Function to periodically pick up new directory names and name unknown entries in the background
+    pub fn spawn_refresh(self: &Arc<Self>, db: Arc<GraphDatabase>, interval: Duration) {
+        let recovery = self.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = recovery.load_directory_names(&db).await {
+                    eprintln!("Failed to load directory names: {}", e);
+                }
+                match recovery.recover_names(&db).await {
+                    Ok(named) if named > 0 => println!("Recovered names for {} discriminators", named),
+                    Ok(_) => {}
+                    Err(e) => eprintln!("Failed to recover names: {}", e),
+                }
+                tokio::time::sleep(interval).await;
+            }
+        });
+    }
+}
+
+impl Default for NameRecovery {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Lists the names whose hash matches a discriminator, optionally only those of one `?kind=`
+pub async fn discriminator_candidates_endpoint(
+    recovery: web::Data<NameRecovery>,
+    discriminator: DiscriminatorParam,
+    filter: web::Query<KindFilter>,
+) -> impl Responder {
+    let kind = filter.into_inner().kind;
+    let candidates: Vec<NameCandidate> = recovery
+        .candidates(&discriminator.bytes)
+        .into_iter()
+        .filter(|candidate| kind.is_none_or(|kind| candidate.kind == kind))
+        .collect();
+
+    HttpResponse::Ok().json(json!({
+        "discriminator": discriminator.hex,
+        "candidates": candidates,
+    }))
+}
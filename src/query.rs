@@ -1,93 +1,366 @@
 use actix_web::{web, HttpRequest, HttpResponse, Responder};
-use serde_json::json;
-use crate::graph_disc::GraphDatabase;
+use serde_json::{json, Value};
+use crate::anchor::discriminator_for;
+use crate::api_keys::charge_cache_miss;
+use crate::auth::{random_hex, AuthenticatedUser};
+use crate::decode::{resolve_defined_types, DataEncoding, IdlField, IdlTypeDefinition};
+use crate::graph_disc::{
+    unix_timestamp, Confidence, DatabaseError, Discriminator, DiscriminatorDetails, DiscriminatorKind, GraphDatabase, JobKind, JobSpec,
+    JobState, Role, Submission, SubmissionStatus,
+};
+use crate::jobs::{job_accepted, job_dedup_key, JobRunner};
+use crate::listener_supervisor::ListenerSupervisor;
+use crate::moderation::{is_trusted, publish_submission, AUTO_APPROVER};
 use crate::solana_connection::SolanaConnection;
+use crate::validation::{DiscriminatorParam, ProgramId};
 use log::{error, info};
+use serde::{Deserialize, Serialize};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+
+// Number of sample payloads returned per event discriminator
+const EVENT_SAMPLE_LIMIT: usize = 5;
+
+// Optional `?kind=instruction|account|event` filter shared by the query routes
+#[derive(Debug, Deserialize)]
+pub struct KindFilter {
+    pub kind: Option<DiscriminatorKind>,
+}
+
+// Filters of the program query: `?kind=` and `?min_confidence=low|medium|high|official`
+#[derive(Debug, Deserialize)]
+pub struct DiscriminatorFilter {
+    kind: Option<DiscriminatorKind>,
+    min_confidence: Option<Confidence>,
+}
+
+// Function to drop entries below the requested confidence
+fn with_min_confidence(discriminators: Vec<Discriminator>, min_confidence: Option<Confidence>) -> Vec<Discriminator> {
+    match min_confidence {
+        Some(min_confidence) => discriminators
+            .into_iter()
+            .filter(|discriminator| discriminator.confidence() >= min_confidence)
+            .collect(),
+        None => discriminators,
+    }
+}
 
 pub async fn query_discriminators_endpoint(
     db: web::Data<GraphDatabase>,
-    solana_client: web::Data<SolanaConnection>,
-    program_id: web::Path<String>,
+    jobs: web::Data<JobRunner>,
+    program_id: ProgramId,
+    filter: web::Query<DiscriminatorFilter>,
+    req: HttpRequest,
 ) -> impl Responder {
     let program_id = program_id.into_inner();
+    let DiscriminatorFilter { kind, min_confidence } = filter.into_inner();
 
     // Check if discriminators are in the database
-    let discriminators = db.query_discriminators_and_instructions(&program_id).await;
+    let discriminators = db.query_discriminators_and_instructions(&program_id, kind).await;
 
     match discriminators {
         Ok(discriminators) => {
-            if !discriminators.len() == 0 {
-                HttpResponse::Ok().json(discriminators)
+            if !discriminators.is_empty() {
+                // The chain is only scraped when nothing is stored, not when the filter leaves nothing
+                HttpResponse::Ok().json(with_min_confidence(discriminators, min_confidence))
+            } else if kind.is_some_and(|kind| kind != DiscriminatorKind::Account) {
+                // Only account discriminators can be recovered from program accounts
+                HttpResponse::NotFound().body("No discriminators found")
             } else {
-                // If not found in DB, fetch from Solana
-                let accounts_result = solana_client.get_program_accounts(&program_id);
-
-                match accounts_result.await {
-                    Ok(accounts) => {
-                        let mut uploaded_any = false;
-                        for (pub_key, account) in accounts {
-
-                            let data = &account.data;
-                            // Extract the discriminator from account data
-                            let discriminator_data = data[0..8].to_vec();
-                            let instruction_data = data[8..].to_vec();
-
-                            if let Err(e) = db.upload_discriminator(
-                                &program_id,
-                                discriminator_data,
-                                instruction_data,
-                                &pub_key.to_string(),
-                            ).await {
-                                println!("Error uploading");
-                                return HttpResponse::InternalServerError().body(e.to_string());
-                            }
-
-                            uploaded_any = true;
+                // If not found in DB, the discriminator prefix of every program account is fetched from Solana
+                // by a background job, which the client polls before querying again
+                match db.latest_job(&job_dedup_key(JobKind::AccountScan, &program_id)).await {
+                    // Joining a scan that is already queued or running costs no RPC calls
+                    Ok(Some(job)) if matches!(job.state, JobState::Queued | JobState::Running) => job_accepted(&job, false),
+                    Ok(Some(job)) if job.found_nothing() => {
+                        HttpResponse::NotFound().body("No discriminators found in Solana accounts")
+                    }
+                    Ok(_) => {
+                        // Scraping program accounts is expensive, so a miss also counts against the cache-miss budget
+                        if let Err(limited) = charge_cache_miss(&req) {
+                            return limited.into();
                         }
-
-                        if uploaded_any {
-                            let disc = db.query_discriminators_and_instructions(&program_id).await;
-                            match disc {
-                                Ok(disc) => HttpResponse::Ok().json(disc),
-                                Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
-                            }
-                        } else {
-                            HttpResponse::NotFound().body("No discriminators found in Solana accounts")
+                        match jobs.enqueue(JobSpec::AccountScan { program_id }, None).await {
+                            Ok((job, added)) => job_accepted(&job, added),
+                            Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
                         }
                     }
                     Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
                 }
             }
         }
+        Err(e @ DatabaseError::NotFound(_)) => HttpResponse::NotFound().body(e.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+
+// Counts the accounts of a single type using a memcmp filter on the hex-encoded discriminator
+pub async fn count_accounts_endpoint(
+    solana_client: web::Data<SolanaConnection>,
+    program_id: ProgramId,
+    discriminator: DiscriminatorParam,
+) -> impl Responder {
+    let program_id = program_id.into_inner();
+    let DiscriminatorParam { hex: discriminator, bytes: discriminator_data } = discriminator;
+
+    match solana_client.interactive().count_accounts_with_discriminator(&program_id, &discriminator_data).await {
+        Ok(count) => HttpResponse::Ok().json(json!({
+            "program_id": program_id,
+            "discriminator": discriminator,
+            "account_count": count,
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e})),
+    }
+}
+
+
+// Lists the event discriminators of a program, or those of another `?kind=`, with base64 sample payloads.
+// `?min_confidence=` drops entries below the given confidence.
+pub async fn query_events_endpoint(
+    db: web::Data<GraphDatabase>,
+    program_id: ProgramId,
+    filter: web::Query<DiscriminatorFilter>,
+) -> impl Responder {
+    let program_id = program_id.into_inner();
+    let DiscriminatorFilter { kind, min_confidence } = filter.into_inner();
+    let kind = kind.unwrap_or(DiscriminatorKind::Event);
+
+    match db.query_events(&program_id, kind, EVENT_SAMPLE_LIMIT).await {
+        Ok(events) => {
+            let events: Vec<_> = events
+                .into_iter()
+                .filter(|event| min_confidence.is_none_or(|min_confidence| event.discriminator.confidence() >= min_confidence))
+                .map(|event| json!({
+                    "discriminator": event.discriminator,
+                    "samples": event.samples.iter().map(|sample| BASE64.encode(sample)).collect::<Vec<_>>(),
+                }))
+                .collect();
+            HttpResponse::Ok().json(events)
+        }
         Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
     }
 }
 
 
+// Upload payload. Fields are loosely typed so every problem can be reported against the field it concerns.
+#[derive(Debug, Deserialize)]
+pub struct UploadDiscriminatorRequest {
+    discriminator: Option<Value>,
+    // "hex" (default), "base58", "base64" or "byte-array"
+    encoding: Option<String>,
+    kind: Option<String>,
+    name: Option<String>,
+    // Borsh layout of the data following the discriminator, as in an Anchor IDL
+    args: Option<Value>,
+    // Definitions of the types `args` refers to with `defined`, as in an Anchor IDL's `types`
+    types: Option<Value>,
+    source: Option<String>,
+    notes: Option<String>,
+}
+
+// A submission that passed validation
+pub struct DiscriminatorSubmission {
+    pub discriminator_data: Vec<u8>,
+    pub kind: DiscriminatorKind,
+    pub name: Option<String>,
+    pub details: DiscriminatorDetails,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FieldError {
+    field: &'static str,
+    message: String,
+}
+
+impl FieldError {
+    fn new(field: &'static str, message: impl Into<String>) -> Self {
+        FieldError { field, message: message.into() }
+    }
+}
+
+// Longest name, source and notes accepted from a submission
+const MAX_NAME_LENGTH: usize = 128;
+const MAX_SOURCE_LENGTH: usize = 256;
+const MAX_NOTES_LENGTH: usize = 2048;
+
+// Function to decode the discriminator bytes of a submission in its declared encoding
+fn decode_discriminator(value: Option<&Value>, encoding: &str) -> Result<Vec<u8>, FieldError> {
+    let value = value.ok_or_else(|| FieldError::new("discriminator", "is required"))?;
+
+    let bytes = if encoding == "byte-array" {
+        serde_json::from_value::<Vec<u8>>(value.clone())
+            .map_err(|_| FieldError::new("discriminator", "must be an array of integers between 0 and 255"))?
+    } else {
+        let text = value
+            .as_str()
+            .ok_or_else(|| FieldError::new("discriminator", format!("must be a {} string", encoding)))?;
+        let encoding: DataEncoding = serde_json::from_value(json!(encoding))
+            .map_err(|_| FieldError::new("encoding", "must be one of hex, base58, base64 or byte-array"))?;
+        encoding
+            .decode(text)
+            .map_err(|e| FieldError::new("discriminator", format!("is not valid {:?}: {}", encoding, e)))?
+    };
+
+    if bytes.len() != 8 {
+        return Err(FieldError::new("discriminator", format!("must be exactly 8 bytes, got {}", bytes.len())));
+    }
+    Ok(bytes)
+}
+
+// Function to check an optional free-text field against its maximum length
+fn check_text(field: &'static str, value: Option<String>, max_length: usize, errors: &mut Vec<FieldError>) -> Option<String> {
+    let value = value.map(|value| value.trim().to_string()).filter(|value| !value.is_empty())?;
+    if value.len() > max_length {
+        errors.push(FieldError::new(field, format!("must be at most {} bytes", max_length)));
+        return None;
+    }
+    Some(value)
+}
+
+impl UploadDiscriminatorRequest {
+    // Function to validate every field, collecting all problems instead of stopping at the first
+    pub fn validate(self) -> Result<DiscriminatorSubmission, Vec<FieldError>> {
+        let mut errors = Vec::new();
+
+        let encoding = self.encoding.as_deref().unwrap_or("hex");
+        let discriminator_data = decode_discriminator(self.discriminator.as_ref(), encoding)
+            .map_err(|e| errors.push(e))
+            .ok();
+
+        let kind = match self.kind.as_deref() {
+            None => Some(DiscriminatorKind::Instruction),
+            Some(kind) => serde_json::from_value(json!(kind))
+                .map_err(|_| errors.push(FieldError::new("kind", "must be one of instruction, account or event")))
+                .ok(),
+        };
+
+        let name = check_text("name", self.name, MAX_NAME_LENGTH, &mut errors);
+        if name.as_ref().is_some_and(|name| !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')) {
+            errors.push(FieldError::new("name", "must be an identifier of letters, digits and underscores"));
+        }
+
+        let types = self
+            .types
+            .map(serde_json::from_value::<Vec<IdlTypeDefinition>>)
+            .transpose()
+            .map_err(|e| errors.push(FieldError::new("types", format!("is not a valid list of IDL type definitions: {}", e))))
+            .unwrap_or(None)
+            .unwrap_or_default();
+
+        let args = self
+            .args
+            .map(serde_json::from_value::<Vec<IdlField>>)
+            .transpose()
+            .map_err(|e| errors.push(FieldError::new("args", format!("is not a valid IDL field list: {}", e))))
+            .unwrap_or(None)
+            .map(|args| resolve_defined_types(&args, &types));
+
+        let source = check_text("source", self.source, MAX_SOURCE_LENGTH, &mut errors);
+        let notes = check_text("notes", self.notes, MAX_NOTES_LENGTH, &mut errors);
+
+        match (discriminator_data, kind) {
+            (Some(discriminator_data), Some(kind)) if errors.is_empty() => Ok(DiscriminatorSubmission {
+                discriminator_data,
+                kind,
+                name,
+                details: DiscriminatorDetails { args, accounts: None, source, notes },
+            }),
+            _ => Err(errors),
+        }
+    }
+}
+
+// Accepts a submission from a signed-in contributor. Trusted users are published right away;
+// everyone else's submissions wait in the moderation queue.
 pub async fn upload_discriminator_endpoint(
     db: web::Data<GraphDatabase>,
-    program_id: web::Path<String>,
-    discriminator_info: web::Json<(String, String, String)>,
-    req: HttpRequest,
+    supervisor: web::Data<ListenerSupervisor>,
+    program_id: ProgramId,
+    submission: web::Json<UploadDiscriminatorRequest>,
+    user: AuthenticatedUser,
 ) -> impl Responder {
     let program_id = program_id.into_inner();
-    let (discriminator, instruction, _) = discriminator_info.into_inner();
-    info!("Uploading discriminator for program_id: {}", program_id);
-
-    // Extract user_id from the headers
-    let user_id = match req.headers().get("user_id") {
-        Some(value) => match value.to_str() {
-            Ok(v) => v.to_string(),
-            Err(_) => return HttpResponse::BadRequest().json(json!({"error": "Invalid user_id header value"})),
-        },
-        None => return HttpResponse::BadRequest().json(json!({"error": "Missing user_id header"})),
+    info!("Uploading discriminator for program_id: {} by {}", program_id, user.user_id);
+
+    if let Err(denied) = user.require(Role::Contributor) {
+        return denied.into();
+    }
+
+    let DiscriminatorSubmission { discriminator_data, kind, name, details } = match submission.into_inner().validate() {
+        Ok(submission) => submission,
+        Err(errors) => return HttpResponse::BadRequest().json(json!({"error": "Invalid submission", "fields": errors})),
+    };
+
+    // Official mappings can only be changed by the program authority
+    match db.get_discriminator(&program_id, kind, &discriminator_data).await {
+        Ok(Some(existing)) if existing.is_official() => {
+            return HttpResponse::Conflict().json(json!({
+                "error": "This entry is an official mapping published by the program authority",
+            }))
+        }
+        Ok(_) => {}
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+
+    let trusted = match is_trusted(&db, &user).await {
+        Ok(trusted) => trusted,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
     };
 
-    match db.upload_discriminator(&program_id, discriminator.into_bytes(), instruction.into_bytes(), &user_id).await {
-        Ok(_) => HttpResponse::Ok().json(json!({"status": "Discriminator uploaded successfully"})),
+    // A submitted name is verified when it hashes to the submitted bytes
+    let name_verified = name.as_ref().map(|name| discriminator_for(kind, name)[..] == discriminator_data[..]);
+
+    // The contributor is the wallet the session was signed in with
+    let mut submission = Submission {
+        _key: random_hex(8),
+        program_id,
+        kind,
+        discriminator_data,
+        name_verified,
+        name,
+        details,
+        user_id: user.user_id,
+        status: SubmissionStatus::Pending,
+        created_at: unix_timestamp(),
+        reviewed_by: None,
+        reviewed_at: None,
+        review_note: None,
+    };
+
+    if !trusted {
+        if let Err(e) = db.create_submission(&submission).await {
+            error!("Error storing submission: {}", e);
+            return HttpResponse::InternalServerError().json(json!({"error": "Failed to store submission"}));
+        }
+        return HttpResponse::Accepted().json(json!({
+            "status": "Submission queued for review",
+            "submission_id": submission._key,
+            "discriminator": hex::encode(&submission.discriminator_data),
+            "kind": kind,
+        }));
+    }
+
+    let verified = match publish_submission(&db, &supervisor, &submission).await {
+        Ok(verified) => verified,
         Err(e) => {
             error!("Error uploading discriminator to DB: {}", e);
-            HttpResponse::InternalServerError().json(json!({"error": "Failed to upload discriminator to DB"}))
+            return HttpResponse::InternalServerError().json(json!({"error": "Failed to upload discriminator to DB"}));
         }
+    };
+
+    submission.status = SubmissionStatus::Approved;
+    submission.reviewed_by = Some(AUTO_APPROVER.to_string());
+    submission.reviewed_at = Some(submission.created_at);
+    if let Err(e) = db.create_submission(&submission).await {
+        error!("Error storing submission: {}", e);
     }
+
+    HttpResponse::Ok().json(json!({
+        "status": "Discriminator uploaded successfully",
+        "submission_id": submission._key,
+        "discriminator": hex::encode(&submission.discriminator_data),
+        "kind": kind,
+        "name_verified": verified,
+    }))
 }
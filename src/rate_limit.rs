@@ -0,0 +1,451 @@
+use actix_web::{web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::solana_connection::SolanaConnection;
+
+// Share of the budget background callers must leave untouched for interactive lookups
+const BACKGROUND_RESERVE_RATIO: f64 = 0.25;
+
+// Classic token bucket: `capacity` tokens, refilled continuously at `refill_per_sec`
+#[derive(Debug)]
+pub struct TokenBucket {
+    capacity: f64,
+    refill_per_sec: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(capacity: f64, refill_per_sec: f64) -> Self {
+        TokenBucket {
+            capacity,
+            refill_per_sec,
+            tokens: capacity,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    pub fn capacity(&self) -> f64 {
+        self.capacity
+    }
+
+    // Function to change the size of the bucket, keeping the same fraction of it filled
+    pub fn resize(&mut self, capacity: f64, refill_per_sec: f64) {
+        self.refill();
+        self.tokens = self.tokens / self.capacity * capacity;
+        self.capacity = capacity;
+        self.refill_per_sec = refill_per_sec;
+    }
+
+    // Function to take `cost` tokens while leaving at least `reserve` in the bucket.
+    // On failure returns how long to wait until enough tokens have been refilled.
+    pub fn try_take(&mut self, cost: f64, reserve: f64) -> Result<(), Duration> {
+        self.refill();
+        let cost = cost.min(self.capacity - reserve);
+        let missing = cost + reserve - self.tokens;
+
+        if missing <= 0.0 {
+            self.tokens -= cost;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(missing / self.refill_per_sec))
+        }
+    }
+
+    pub fn available(&mut self) -> f64 {
+        self.refill();
+        self.tokens
+    }
+}
+
+// Who is asking for an RPC call; interactive HTTP lookups go ahead of background listeners
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RpcPriority {
+    Interactive,
+    Background,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MethodMetrics {
+    calls: u64,
+    throttled: u64,
+    throttled_interactive: u64,
+    throttled_background: u64,
+    total_wait_ms: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RpcBudgetMetrics {
+    requests_per_second: f64,
+    available_tokens: f64,
+    methods: HashMap<String, MethodMetrics>,
+}
+
+// Global requests-per-second budget shared by every RPC call made through `SolanaConnection`
+pub struct RpcBudget {
+    requests_per_second: f64,
+    bucket: Mutex<TokenBucket>,
+    interactive_waiting: AtomicUsize,
+    metrics: Mutex<HashMap<&'static str, MethodMetrics>>,
+}
+
+impl RpcBudget {
+    // The bucket holds two seconds worth of requests so short bursts are not throttled
+    pub fn new(requests_per_second: f64) -> Self {
+        RpcBudget {
+            requests_per_second,
+            bucket: Mutex::new(TokenBucket::new(requests_per_second * 2.0, requests_per_second)),
+            interactive_waiting: AtomicUsize::new(0),
+            metrics: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Relative cost of each RPC method in budget tokens
+    pub fn method_cost(method: &str) -> f64 {
+        match method {
+            "getProgramAccounts" => 20.0,
+            "getMultipleAccounts" => 5.0,
+            "getTransaction" => 2.0,
+            "getSignaturesForAddress" | "getAccountInfo" => 1.0,
+            "getHealth" => 0.0,
+            _ => 1.0,
+        }
+    }
+
+    // Function to wait until the budget allows a call to `method`
+    pub async fn acquire(&self, method: &'static str, priority: RpcPriority) {
+        let cost = Self::method_cost(method);
+        let mut waited = Duration::ZERO;
+        let mut throttled = false;
+
+        // Registered for the whole wait, and released even if the caller gives up
+        let _waiting = (priority == RpcPriority::Interactive).then(|| WaitingGuard::new(&self.interactive_waiting));
+
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock().unwrap();
+                let result = match priority {
+                    RpcPriority::Interactive => bucket.try_take(cost, 0.0),
+                    RpcPriority::Background => {
+                        // Background calls yield to any interactive call that is waiting
+                        if self.interactive_waiting.load(Ordering::SeqCst) > 0 {
+                            Err(Duration::from_millis(50))
+                        } else {
+                            let reserve = bucket.capacity() * BACKGROUND_RESERVE_RATIO;
+                            bucket.try_take(cost, reserve)
+                        }
+                    }
+                };
+                result.err()
+            };
+
+            match wait {
+                None => break,
+                Some(delay) => {
+                    throttled = true;
+                    waited += delay;
+                    tokio::time::sleep(delay).await;
+                }
+            }
+        }
+
+        let mut metrics = self.metrics.lock().unwrap();
+        let entry = metrics.entry(method).or_default();
+        entry.calls += 1;
+        if throttled {
+            entry.throttled += 1;
+            entry.total_wait_ms += waited.as_millis() as u64;
+            match priority {
+                RpcPriority::Interactive => entry.throttled_interactive += 1,
+                RpcPriority::Background => entry.throttled_background += 1,
+            }
+        }
+    }
+
+    // Function to take a snapshot of the budget and per-method throttling metrics
+    pub fn metrics(&self) -> RpcBudgetMetrics {
+        let available_tokens = self.bucket.lock().unwrap().available();
+        let methods = self
+            .metrics
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(method, metrics)| (method.to_string(), metrics.clone()))
+            .collect();
+
+        RpcBudgetMetrics {
+            requests_per_second: self.requests_per_second,
+            available_tokens,
+            methods,
+        }
+    }
+}
+
+// Counts an interactive caller as waiting for as long as the guard is alive
+struct WaitingGuard<'a>(&'a AtomicUsize);
+
+impl<'a> WaitingGuard<'a> {
+    fn new(counter: &'a AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::SeqCst);
+        WaitingGuard(counter)
+    }
+}
+
+impl Drop for WaitingGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Budget an HTTP request is charged against
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RequestClass {
+    Read,
+    Write,
+    // Requests that trigger RPC calls, e.g. a directory miss that scrapes program accounts
+    CacheMiss,
+}
+
+impl RequestClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RequestClass::Read => "read",
+            RequestClass::Write => "write",
+            RequestClass::CacheMiss => "cache_miss",
+        }
+    }
+}
+
+// Requests per minute allowed in each class
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RequestQuotas {
+    pub reads_per_minute: u32,
+    pub writes_per_minute: u32,
+    pub cache_misses_per_minute: u32,
+}
+
+impl RequestQuotas {
+    // Applied per IP address to requests without an API key
+    pub const ANONYMOUS: RequestQuotas = RequestQuotas {
+        reads_per_minute: 120,
+        writes_per_minute: 20,
+        cache_misses_per_minute: 5,
+    };
+
+    // Given to newly issued API keys
+    pub const API_KEY: RequestQuotas = RequestQuotas {
+        reads_per_minute: 600,
+        writes_per_minute: 120,
+        cache_misses_per_minute: 30,
+    };
+
+    pub fn per_minute(&self, class: RequestClass) -> u32 {
+        match class {
+            RequestClass::Read => self.reads_per_minute,
+            RequestClass::Write => self.writes_per_minute,
+            RequestClass::CacheMiss => self.cache_misses_per_minute,
+        }
+    }
+}
+
+// Who a request is counted against: its API key, or its IP address when it has none
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ClientIdentity {
+    ApiKey(String),
+    Ip(String),
+}
+
+// Remaining allowance after a request was let through
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimitStatus {
+    pub limit: u32,
+    pub remaining: u32,
+}
+
+// Number of tracked buckets above which idle ones are dropped
+const MAX_TRACKED_BUCKETS: usize = 50_000;
+
+// Per-client token buckets for incoming HTTP requests, one for each request class
+pub struct RequestLimiter {
+    buckets: Mutex<HashMap<(ClientIdentity, RequestClass), TokenBucket>>,
+}
+
+impl RequestLimiter {
+    pub fn new() -> Self {
+        RequestLimiter {
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Function to charge one request of `class` to a client.
+    // Buckets hold a full minute of requests, so clients may burst up to their quota.
+    // On failure returns how long the client has to wait.
+    pub fn check(
+        &self,
+        identity: &ClientIdentity,
+        class: RequestClass,
+        quotas: &RequestQuotas,
+    ) -> Result<RateLimitStatus, Duration> {
+        let limit = quotas.per_minute(class);
+        if limit == 0 {
+            return Err(Duration::from_secs(60));
+        }
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_TRACKED_BUCKETS {
+            // Full buckets belong to clients that have been idle long enough to have no history worth keeping
+            buckets.retain(|_, bucket| bucket.available() < bucket.capacity());
+        }
+
+        let bucket = buckets
+            .entry((identity.clone(), class))
+            .or_insert_with(|| TokenBucket::new(limit as f64, limit as f64 / 60.0));
+        // Quotas of a key can change while its bucket is alive; a new quota does not hand out a fresh burst
+        if bucket.capacity() != limit as f64 {
+            bucket.resize(limit as f64, limit as f64 / 60.0);
+        }
+
+        bucket.try_take(1.0, 0.0)?;
+        Ok(RateLimitStatus {
+            limit,
+            remaining: bucket.available().floor() as u32,
+        })
+    }
+}
+
+impl Default for RequestLimiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub async fn rpc_metrics_endpoint(solana_client: web::Data<SolanaConnection>) -> impl Responder {
+    HttpResponse::Ok().json(solana_client.budget_metrics())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUOTAS: RequestQuotas = RequestQuotas {
+        reads_per_minute: 2,
+        writes_per_minute: 1,
+        cache_misses_per_minute: 0,
+    };
+
+    // Function to pretend `elapsed` has passed since the bucket was last refilled
+    fn age(bucket: &mut TokenBucket, elapsed: Duration) {
+        bucket.last_refill -= elapsed;
+    }
+
+    #[test]
+    fn takes_tokens_until_empty_and_reports_the_wait() {
+        let mut bucket = TokenBucket::new(2.0, 1.0);
+
+        assert!(bucket.try_take(1.0, 0.0).is_ok());
+        assert!(bucket.try_take(1.0, 0.0).is_ok());
+        let wait = bucket.try_take(1.0, 0.0).unwrap_err();
+        assert!(wait > Duration::from_millis(900) && wait <= Duration::from_secs(1), "waited {:?}", wait);
+    }
+
+    #[test]
+    fn refills_over_time_up_to_capacity() {
+        let mut bucket = TokenBucket::new(4.0, 2.0);
+        bucket.try_take(4.0, 0.0).unwrap();
+
+        age(&mut bucket, Duration::from_secs(1));
+        assert!((bucket.available() - 2.0).abs() < 0.01);
+
+        age(&mut bucket, Duration::from_secs(60));
+        assert_eq!(bucket.available(), 4.0);
+    }
+
+    #[test]
+    fn keeps_the_filled_fraction_when_resized() {
+        let mut bucket = TokenBucket::new(10.0, 1.0);
+        bucket.try_take(5.0, 0.0).unwrap();
+
+        bucket.resize(20.0, 2.0);
+        assert_eq!(bucket.capacity(), 20.0);
+        assert!((bucket.available() - 10.0).abs() < 0.01);
+
+        bucket.resize(4.0, 1.0);
+        assert!((bucket.available() - 2.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn leaves_the_reserve_in_the_bucket() {
+        let mut bucket = TokenBucket::new(4.0, 1.0);
+
+        assert!(bucket.try_take(2.0, 1.0).is_ok());
+        assert!(bucket.try_take(2.0, 1.0).is_err());
+        // The reserve is only held back from callers that ask for one
+        assert!(bucket.try_take(2.0, 0.0).is_ok());
+    }
+
+    #[tokio::test]
+    async fn background_calls_leave_the_reserve_for_interactive_ones() {
+        // Two tokens, of which a quarter is reserved, refilled at one per second
+        let budget = RpcBudget::new(1.0);
+        let acquire = |priority| tokio::time::timeout(Duration::from_millis(100), budget.acquire("getAccountInfo", priority));
+
+        assert!(acquire(RpcPriority::Background).await.is_ok());
+        assert!(acquire(RpcPriority::Background).await.is_err());
+        assert!(acquire(RpcPriority::Interactive).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn background_calls_yield_to_waiting_interactive_ones() {
+        let budget = RpcBudget::new(100.0);
+
+        let waiting = WaitingGuard::new(&budget.interactive_waiting);
+        let blocked = tokio::time::timeout(Duration::from_millis(100), budget.acquire("getAccountInfo", RpcPriority::Background)).await;
+        assert!(blocked.is_err());
+
+        drop(waiting);
+        let resumed = tokio::time::timeout(Duration::from_millis(100), budget.acquire("getAccountInfo", RpcPriority::Background)).await;
+        assert!(resumed.is_ok());
+    }
+
+    #[test]
+    fn limits_each_client_and_class_separately() {
+        let limiter = RequestLimiter::new();
+        let key = ClientIdentity::ApiKey("key".to_string());
+        let ip = ClientIdentity::Ip("127.0.0.1".to_string());
+
+        assert_eq!(limiter.check(&key, RequestClass::Read, &QUOTAS).unwrap().remaining, 1);
+        assert_eq!(limiter.check(&key, RequestClass::Read, &QUOTAS).unwrap().remaining, 0);
+        assert!(limiter.check(&key, RequestClass::Read, &QUOTAS).is_err());
+
+        assert!(limiter.check(&key, RequestClass::Write, &QUOTAS).is_ok());
+        assert!(limiter.check(&ip, RequestClass::Read, &QUOTAS).is_ok());
+        // A class with no quota is always refused
+        assert!(limiter.check(&ip, RequestClass::CacheMiss, &QUOTAS).is_err());
+    }
+
+    #[test]
+    fn a_raised_quota_does_not_hand_out_a_fresh_burst() {
+        let limiter = RequestLimiter::new();
+        let key = ClientIdentity::ApiKey("key".to_string());
+        limiter.check(&key, RequestClass::Read, &QUOTAS).unwrap();
+
+        // Half of the old bucket was left, so half of the new one is
+        let raised = RequestQuotas { reads_per_minute: 8, ..QUOTAS };
+        let status = limiter.check(&key, RequestClass::Read, &raised).unwrap();
+        assert_eq!(status.limit, 8);
+        assert_eq!(status.remaining, 3);
+    }
+}
@@ -0,0 +1,197 @@
+use actix_web::{web, HttpResponse, Responder};
+use serde::Serialize;
+use serde_json::json;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use crate::auth::AuthenticatedUser;
+use crate::graph_disc::{DatabaseError, GraphDatabase, Role, SubmissionStatus, SubmissionWithCompetitors};
+use crate::query::KindFilter;
+use crate::validation::{is_valid_pubkey, DiscriminatorParam, ProgramId};
+
+// Points per submission outcome. Names that hash to their discriminator are worth the most, since they
+// are provably right; rejected submissions cost more than a retraction, which the contributor chose.
+const APPROVED_POINTS: i64 = 1;
+const VERIFIED_POINTS: i64 = 3;
+const AGREED_POINTS: i64 = 2;
+const DISPUTED_POINTS: i64 = -1;
+const REJECTED_POINTS: i64 = -3;
+const RETRACTED_POINTS: i64 = -1;
+
+// Number of directory entries listed on a profile
+const PROFILE_CONTRIBUTION_LIMIT: usize = 100;
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Reputation {
+    pub score: i64,
+    pub approved: u64,
+    // Approved names that hash to their discriminator
+    pub verified: u64,
+    // Approved names another user submitted as well
+    pub agreed: u64,
+    // Unverified approved names another user submitted a different name for
+    pub disputed: u64,
+    pub rejected: u64,
+    pub retracted: u64,
+    pub pending: u64,
+}
+
+// Function to score a user's submissions. Only approved submissions earn points for verification and agreement.
+pub fn score_submissions(entries: &[SubmissionWithCompetitors]) -> Reputation {
+    let mut reputation = Reputation::default();
+
+    for SubmissionWithCompetitors { submission, other_names } in entries {
+        match submission.status {
+            SubmissionStatus::Pending | SubmissionStatus::Publishing => reputation.pending += 1,
+            SubmissionStatus::Rejected => reputation.rejected += 1,
+            SubmissionStatus::Retracted => reputation.retracted += 1,
+            SubmissionStatus::Approved => {
+                reputation.approved += 1;
+                let verified = submission.name_verified == Some(true);
+                if verified {
+                    reputation.verified += 1;
+                }
+                if let Some(name) = &submission.name {
+                    if other_names.contains(name) {
+                        reputation.agreed += 1;
+                    } else if !other_names.is_empty() && !verified {
+                        reputation.disputed += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    reputation.score = reputation.approved as i64 * APPROVED_POINTS
+        + reputation.verified as i64 * VERIFIED_POINTS
+        + reputation.agreed as i64 * AGREED_POINTS
+        + reputation.disputed as i64 * DISPUTED_POINTS
+        + reputation.rejected as i64 * REJECTED_POINTS
+        + reputation.retracted as i64 * RETRACTED_POINTS;
+    reputation
+}
+
+// Function to compute the current reputation of several users with a single query
+pub async fn compute_reputations(db: &GraphDatabase, user_ids: &[String]) -> Result<HashMap<String, Reputation>, DatabaseError> {
+    let mut by_user: HashMap<String, Vec<SubmissionWithCompetitors>> = HashMap::new();
+    for entry in db.get_submissions_with_competitors(user_ids).await? {
+        by_user.entry(entry.submission.user_id.clone()).or_default().push(entry);
+    }
+
+    Ok(user_ids
+        .iter()
+        .map(|user_id| {
+            let entries = by_user.remove(user_id).unwrap_or_default();
+            (user_id.clone(), score_submissions(&entries))
+        })
+        .collect())
+}
+
+// A name proposed for a discriminator with everyone who proposed it
+#[derive(Debug, Serialize)]
+pub struct RankedName {
+    name: String,
+    verified: bool,
+    official: bool,
+    submitters: Vec<String>,
+    // Sum of the submitters' positive reputations; new or negative reputations add nothing
+    support: i64,
+}
+
+// Shows a user's reputation, role and contributions
+pub async fn user_profile_endpoint(db: web::Data<GraphDatabase>, user_id: web::Path<String>) -> impl Responder {
+    let user_id = user_id.into_inner();
+    if !is_valid_pubkey(&user_id) {
+        return HttpResponse::BadRequest().json(json!({"error": "Invalid user id"}));
+    }
+
+    let entries = match db.get_submissions_with_competitors(std::slice::from_ref(&user_id)).await {
+        Ok(entries) => entries,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    };
+    let role = match db.get_user_role(&user_id).await {
+        Ok(role) => role,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    };
+    let contributions = match db.get_contributions(&user_id, PROFILE_CONTRIBUTION_LIMIT).await {
+        Ok(contributions) => contributions,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    };
+
+    let reputation = score_submissions(&entries);
+    let submissions: Vec<_> = entries.into_iter().map(|entry| entry.submission).collect();
+
+    HttpResponse::Ok().json(json!({
+        "user_id": user_id,
+        "role": role,
+        "reputation": reputation,
+        "contributions": contributions,
+        "submissions": submissions,
+    }))
+}
+
+// Lists the names submitted for a discriminator, best first: the official name, then verified names,
+// then by the combined reputation of the users who submitted them.
+// Only approved submissions are listed, unless a moderator asks, who also sees the pending ones.
+pub async fn ranked_names_endpoint(
+    db: web::Data<GraphDatabase>,
+    user: Option<AuthenticatedUser>,
+    program_id: ProgramId,
+    discriminator: DiscriminatorParam,
+    filter: web::Query<KindFilter>,
+) -> impl Responder {
+    let program_id = program_id.into_inner();
+    let kind = filter.kind.unwrap_or_default();
+    let include_pending = user.is_some_and(|user| user.require(Role::Moderator).is_ok());
+
+    let submissions = match db.get_competing_submissions(&program_id, kind, &discriminator.bytes, include_pending).await {
+        Ok(submissions) => submissions,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    };
+    let official_name = match db.get_discriminator(&program_id, kind, &discriminator.bytes).await {
+        Ok(entry) => entry
+            .filter(|entry| entry.is_official())
+            .and_then(|entry| entry.name().map(|name| name.name.clone())),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    };
+
+    let mut submitters: Vec<String> = submissions.iter().map(|submission| submission.user_id.clone()).collect();
+    submitters.sort();
+    submitters.dedup();
+    let reputations = match compute_reputations(&db, &submitters).await {
+        Ok(reputations) => reputations,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    };
+
+    let mut ranked: Vec<RankedName> = Vec::new();
+    for submission in submissions {
+        let Some(name) = submission.name else { continue };
+
+        // Wallets are free to create, so only an earned reputation carries weight
+        let weight = reputations.get(&submission.user_id).map_or(0, |reputation| reputation.score.max(0));
+
+        match ranked.iter_mut().find(|candidate| candidate.name == name) {
+            Some(candidate) if candidate.submitters.contains(&submission.user_id) => {}
+            Some(candidate) => {
+                candidate.submitters.push(submission.user_id);
+                candidate.support += weight;
+            }
+            None => ranked.push(RankedName {
+                official: official_name.as_ref() == Some(&name),
+                verified: submission.name_verified == Some(true),
+                name,
+                submitters: vec![submission.user_id],
+                support: weight,
+            }),
+        }
+    }
+
+    ranked.sort_by_key(|candidate| Reverse((candidate.official, candidate.verified, candidate.support)));
+
+    HttpResponse::Ok().json(json!({
+        "program_id": program_id,
+        "discriminator": discriminator.hex,
+        "kind": kind,
+        "names": ranked,
+    }))
+}
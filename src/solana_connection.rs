@@ -1,168 +1,897 @@
+use std::collections::HashMap;
 use std::error::Error;
+use std::future::Future;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::Arc;
-use solana_client::{rpc_client::RpcClient, rpc_response::RpcConfirmedTransactionStatusWithSignature};
+use std::time::Duration;
+use futures::StreamExt;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use solana_account_decoder::{UiAccountEncoding, UiDataSliceConfig};
+use solana_client::client_error::{ClientError, ClientErrorKind};
+use solana_client::nonblocking::pubsub_client::PubsubClient;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
+use solana_client::rpc_config::{
+    RpcAccountInfoConfig, RpcProgramAccountsConfig, RpcSimulateTransactionConfig, RpcTransactionConfig,
+    RpcTransactionLogsConfig, RpcTransactionLogsFilter,
+};
+use solana_client::rpc_filter::{Memcmp, RpcFilterType};
+use solana_client::rpc_request::{RpcError, RpcRequest};
+use solana_client::rpc_response::{
+    OptionalContext, RpcConfirmedTransactionStatusWithSignature, RpcKeyedAccount, RpcSimulateTransactionResult,
+};
 use solana_sdk::account::Account;
+use solana_sdk::address_lookup_table::state::AddressLookupTable;
+use solana_sdk::bpf_loader_upgradeable::{self, UpgradeableLoaderState};
+use solana_sdk::{bpf_loader, bpf_loader_deprecated};
 use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::message::VersionedMessage;
 use solana_sdk::signature::Signature;
-use solana_transaction_status::UiTransactionEncoding;
+use solana_sdk::transaction::VersionedTransaction;
+use solana_transaction_status::{EncodedConfirmedTransactionWithStatusMeta, UiTransactionEncoding};
 use solana_sdk::pubkey::Pubkey;
-use tokio::task;
+use tokio_retry::strategy::{jitter, ExponentialBackoff};
+use tokio_retry::RetryIf;
 
-use crate::graph_disc::GraphDatabase; // Import tokio task for blocking operations
+use crate::ingest::split_discriminator;
+use crate::rate_limit::{RpcBudget, RpcBudgetMetrics, RpcPriority};
 
-pub struct SolanaConnection {
+// Delay before reconnecting to PubSub, doubled after every connection that delivered nothing
+const INITIAL_RECONNECT_BACKOFF: Duration = Duration::from_secs(1);
+// Upper bound for the delay between PubSub reconnect attempts
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(60);
+// Most signatures `getSignaturesForAddress` returns per call
+const SIGNATURE_PAGE_SIZE: usize = 1000;
+// Number of retries after the first attempt of an RPC call, each one going to the next healthiest endpoint
+const MAX_RPC_RETRIES: usize = 3;
+// An endpoint with this many consecutive failures is only used when every other endpoint is failing too
+const UNHEALTHY_FAILURE_THRESHOLD: u32 = 3;
+
+// A single RPC endpoint and its health as observed by recent calls
+struct RpcEndpoint {
+    url: String,
+    ws_url: String,
     client: Arc<RpcClient>,
+    consecutive_failures: AtomicU32,
+}
+
+impl RpcEndpoint {
+    fn is_healthy(&self) -> bool {
+        self.consecutive_failures.load(Ordering::Relaxed) < UNHEALTHY_FAILURE_THRESHOLD
+    }
+
+    fn record_success(&self) {
+        self.consecutive_failures.store(0, Ordering::Relaxed);
+    }
+
+    fn record_failure(&self) {
+        self.consecutive_failures.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// Where a listener stores what it observes. The directory database is the real sink.
+pub trait ListenerSink: Send + Sync {
+    // Function to store the discriminators a transaction of the program executed
+    fn store_transaction(
+        &self,
+        program_id: &str,
+        signature: &str,
+        transaction: &EncodedConfirmedTransactionWithStatusMeta,
+    ) -> impl Future<Output = Result<(), Box<dyn Error + Send + Sync>>> + Send;
+
+    // Function to store the discriminator of an account the program owns
+    fn store_account(
+        &self,
+        program_id: &str,
+        account: &str,
+        discriminator: Vec<u8>,
+    ) -> impl Future<Output = Result<(), Box<dyn Error + Send + Sync>>> + Send;
+}
+
+pub struct SolanaConnection {
+    endpoints: Arc<Vec<RpcEndpoint>>,
+    budget: Arc<RpcBudget>,
+    priority: RpcPriority,
 }
 
 impl SolanaConnection {
-    pub fn new(url: &str) -> Self {
-        let client = Arc::new(RpcClient::new_with_commitment(url.to_string(), CommitmentConfig::confirmed()));
-        SolanaConnection { client }
+    // Endpoints are tried in the given order while they are healthy.
+    // All calls share a budget of `requests_per_second`, and run at background priority unless
+    // made through `interactive()`.
+    pub fn new(urls: &[&str], requests_per_second: f64) -> Self {
+        assert!(!urls.is_empty(), "at least one RPC endpoint is required");
+
+        let endpoints = urls
+            .iter()
+            .map(|url| RpcEndpoint {
+                url: url.to_string(),
+                ws_url: websocket_url_for(url),
+                client: Arc::new(RpcClient::new_with_commitment(url.to_string(), CommitmentConfig::confirmed())),
+                consecutive_failures: AtomicU32::new(0),
+            })
+            .collect();
+
+        SolanaConnection {
+            endpoints: Arc::new(endpoints),
+            budget: Arc::new(RpcBudget::new(requests_per_second)),
+            priority: RpcPriority::Background,
+        }
+    }
+
+    // Function to get a handle whose calls go ahead of background listeners, for HTTP lookups
+    pub fn interactive(&self) -> Self {
+        SolanaConnection {
+            priority: RpcPriority::Interactive,
+            ..self.clone()
+        }
+    }
+
+    // Function to get a snapshot of the shared RPC budget
+    pub fn budget_metrics(&self) -> RpcBudgetMetrics {
+        self.budget.metrics()
     }
 
-    // Use spawn_blocking for RPC calls
-    pub async fn get_program_accounts(&self, program_id: &str) -> Result<Vec<(Pubkey, Account)>, String> {
+    // Function to order the endpoints by health, keeping the configured order among equals
+    fn endpoints_by_health(&self) -> Vec<&RpcEndpoint> {
+        let mut endpoints: Vec<&RpcEndpoint> = self.endpoints.iter().collect();
+        endpoints.sort_by_key(|endpoint| (!endpoint.is_healthy(), endpoint.consecutive_failures.load(Ordering::Relaxed)));
+        endpoints
+    }
+
+    // Function to run an RPC call with retries, failing over to the next healthiest endpoint on transient errors
+    async fn call<T, F, Fut>(&self, method: &'static str, request: F) -> Result<T, ClientError>
+    where
+        F: Fn(Arc<RpcClient>) -> Fut,
+        Fut: Future<Output = Result<T, ClientError>>,
+    {
+        let endpoints = self.endpoints_by_health();
+        let strategy = ExponentialBackoff::from_millis(2)
+            .factor(100)
+            .max_delay(Duration::from_secs(2))
+            .map(jitter)
+            .take(MAX_RPC_RETRIES);
+        let mut attempt = 0;
+
+        RetryIf::start(
+            strategy,
+            || {
+                let endpoint = endpoints[attempt % endpoints.len()];
+                attempt += 1;
+                let request = &request;
+                async move {
+                    // Every attempt, including retries, is paid for from the shared budget
+                    self.budget.acquire(method, self.priority).await;
+                    match request(endpoint.client.clone()).await {
+                        Ok(result) => {
+                            endpoint.record_success();
+                            info!("{} served by {}", method, endpoint.url);
+                            Ok(result)
+                        }
+                        Err(e) => {
+                            if is_transient(&e) {
+                                endpoint.record_failure();
+                            }
+                            warn!("{} failed on {}: {}", method, endpoint.url, e);
+                            Err(e)
+                        }
+                    }
+                }
+            },
+            is_transient,
+        )
+        .await
+    }
+
+    // Function to probe every endpoint with `getHealth` and update its health accordingly
+    pub async fn check_health(&self) {
+        for endpoint in self.endpoints.iter() {
+            match endpoint.client.get_health().await {
+                Ok(()) => endpoint.record_success(),
+                Err(e) => {
+                    endpoint.record_failure();
+                    warn!("Health check failed for {}: {}", endpoint.url, e);
+                }
+            }
+        }
+    }
+
+    // Function to keep probing endpoint health in the background
+    pub fn spawn_health_checks(&self, interval: Duration) {
+        let connection = self.clone();
+        tokio::spawn(async move {
+            loop {
+                connection.check_health().await;
+                tokio::time::sleep(interval).await;
+            }
+        });
+    }
+
+    pub async fn get_account(&self, address: &str) -> Result<Account, String> {
+        let address = Pubkey::from_str(address).map_err(|e| e.to_string())?;
+
+        self.call("getAccountInfo", |client| async move {
+            client.get_account(&address).await
+        }).await.map_err(|e| e.to_string())
+    }
+
+    // Function to fetch several accounts in one getMultipleAccounts call, in the order given.
+    // Accounts that do not exist come back as `None`.
+    pub async fn get_multiple_accounts(&self, addresses: &[String]) -> Result<Vec<Option<Account>>, String> {
+        let pubkeys = addresses
+            .iter()
+            .map(|address| Pubkey::from_str(address))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+
+        self.call("getMultipleAccounts", |client| {
+            let pubkeys = pubkeys.clone();
+            async move { client.get_multiple_accounts(&pubkeys).await }
+        }).await.map_err(|e| e.to_string())
+    }
+
+    // Function to resolve the addresses a v0 message loads from address lookup tables.
+    // Returns the writable and the readonly addresses, each in the order the message references them.
+    pub async fn resolve_lookup_tables(&self, message: &VersionedMessage) -> Result<(Vec<String>, Vec<String>), String> {
+        let mut writable = Vec::new();
+        let mut readonly = Vec::new();
+
+        for lookup in message.address_table_lookups().unwrap_or_default() {
+            let account = self.get_account(&lookup.account_key.to_string()).await?;
+            let table = AddressLookupTable::deserialize(&account.data)
+                .map_err(|e| format!("{} is not an address lookup table: {}", lookup.account_key, e))?;
+
+            for (indexes, loaded) in [(&lookup.writable_indexes, &mut writable), (&lookup.readonly_indexes, &mut readonly)] {
+                for index in indexes {
+                    let address = table.addresses.get(*index as usize).ok_or_else(|| {
+                        format!("Lookup table {} has no address at index {}", lookup.account_key, index)
+                    })?;
+                    loaded.push(address.to_string());
+                }
+            }
+        }
+
+        Ok((writable, readonly))
+    }
+
+    // Function to fetch the deployed ELF of a program.
+    // Upgradeable programs keep it in their ProgramData account, after the loader metadata.
+    pub async fn get_program_binary(&self, program_id: &str) -> Result<Vec<u8>, String> {
+        let program = self.get_account(program_id).await?;
+
+        if program.owner == bpf_loader_upgradeable::id() {
+            let programdata_address = match bincode::deserialize(&program.data) {
+                Ok(UpgradeableLoaderState::Program { programdata_address }) => programdata_address,
+                _ => return Err(format!("{} is not an upgradeable program account", program_id)),
+            };
+            let programdata = self.get_account(&programdata_address.to_string()).await?;
+            let metadata_size = UpgradeableLoaderState::size_of_programdata_metadata();
+
+            programdata
+                .data
+                .get(metadata_size..)
+                .map(|elf| elf.to_vec())
+                .ok_or_else(|| format!("ProgramData account of {} is truncated", program_id))
+        } else if program.owner == bpf_loader::id() || program.owner == bpf_loader_deprecated::id() {
+            Ok(program.data)
+        } else {
+            Err(format!("{} is not owned by a BPF loader", program_id))
+        }
+    }
+
+    // Function to read the upgrade authority of a program from its ProgramData account.
+    // Returns `None` for immutable programs and programs not deployed with the upgradeable loader.
+    pub async fn get_upgrade_authority(&self, program_id: &str) -> Result<Option<String>, String> {
+        let program = self.get_account(program_id).await?;
+        if program.owner != bpf_loader_upgradeable::id() {
+            return Ok(None);
+        }
+
+        let programdata_address = match bincode::deserialize(&program.data) {
+            Ok(UpgradeableLoaderState::Program { programdata_address }) => programdata_address,
+            _ => return Err(format!("{} is not an upgradeable program account", program_id)),
+        };
+        let programdata = self.get_account(&programdata_address.to_string()).await?;
+        let metadata = programdata
+            .data
+            .get(..UpgradeableLoaderState::size_of_programdata_metadata())
+            .ok_or_else(|| format!("ProgramData account of {} is truncated", program_id))?;
+
+        match bincode::deserialize(metadata) {
+            Ok(UpgradeableLoaderState::ProgramData { upgrade_authority_address, .. }) => {
+                Ok(upgrade_authority_address.map(|authority| authority.to_string()))
+            }
+            _ => Err(format!("ProgramData account of {} is malformed", program_id)),
+        }
+    }
+
+    // Function to count a program's accounts per distinct discriminator.
+    // Only the first 8 bytes of every account are downloaded; shorter accounts are skipped.
+    pub async fn get_account_discriminator_counts(&self, program_id: &str) -> Result<Vec<AccountDiscriminatorCount>, String> {
         let program_id = Pubkey::from_str(program_id).map_err(|e| e.to_string())?;
-        let client = self.client.clone();
+        let config = RpcProgramAccountsConfig {
+            account_config: RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                data_slice: Some(UiDataSliceConfig { offset: 0, length: 8 }),
+                commitment: Some(CommitmentConfig::confirmed()),
+                ..RpcAccountInfoConfig::default()
+            },
+            ..RpcProgramAccountsConfig::default()
+        };
+
+        // Requested raw, since the sliced data no longer tells the account's length but `space` does
+        let accounts = self.call("getProgramAccounts", |client| {
+            let params = json!([program_id.to_string(), config]);
+            async move {
+                client
+                    .send::<OptionalContext<Vec<RpcKeyedAccount>>>(RpcRequest::GetProgramAccounts, params)
+                    .await
+                    .map(OptionalContext::parse_value)
+            }
+        }).await.map_err(|e| e.to_string())?;
 
-        // Move the RPC call to a blocking task
-        task::spawn_blocking(move || {
-            client.get_program_accounts(&program_id).map_err(|e| e.to_string())
-        }).await.map_err(|e| e.to_string())?
+        let mut counts: HashMap<Vec<u8>, AccountDiscriminatorCount> = HashMap::new();
+        for keyed in accounts {
+            let Some(account) = keyed.account.decode::<Account>() else {
+                continue;
+            };
+            let Some((discriminator, _)) = split_discriminator(&account.data) else {
+                continue;
+            };
+            let entry = counts
+                .entry(discriminator.clone())
+                .or_insert_with(|| AccountDiscriminatorCount {
+                    discriminator,
+                    count: 0,
+                    spaces: Vec::new(),
+                    sample_account: keyed.pubkey.clone(),
+                });
+            entry.count += 1;
+            if let Some(space) = keyed.account.space {
+                if let Err(index) = entry.spaces.binary_search(&space) {
+                    entry.spaces.insert(index, space);
+                }
+            }
+        }
+
+        let mut counts: Vec<AccountDiscriminatorCount> = counts.into_values().collect();
+        counts.sort_by_key(|count| std::cmp::Reverse(count.count));
+        Ok(counts)
     }
-    
-    
 
-    pub async fn get_transactions(
-        &self, 
-        program_id: &str
-    ) -> Result<Vec<RpcConfirmedTransactionStatusWithSignature>, Box<dyn std::error::Error + Send + Sync>> {
-        let client = self.client.clone();
-        let program_pubkey = match Pubkey::from_str(program_id) {
-            Ok(pubkey) => pubkey,
-            Err(e) => return Err(e.to_string().into()),
+    // Function to count the accounts of a single type using a `memcmp` filter on the discriminator.
+    // No account data is downloaded at all.
+    pub async fn count_accounts_with_discriminator(&self, program_id: &str, discriminator: &[u8]) -> Result<u64, String> {
+        let program_id = Pubkey::from_str(program_id).map_err(|e| e.to_string())?;
+        let config = RpcProgramAccountsConfig {
+            filters: Some(vec![RpcFilterType::Memcmp(Memcmp::new_raw_bytes(0, discriminator.to_vec()))]),
+            account_config: RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                data_slice: Some(UiDataSliceConfig { offset: 0, length: 0 }),
+                ..RpcAccountInfoConfig::default()
+            },
+            ..RpcProgramAccountsConfig::default()
         };
-    
-        // Use spawn_blocking to handle the synchronous part of this call.
-        let signatures = task::spawn_blocking(move || {
-            client.get_signatures_for_address(&program_pubkey)
-        }).await??; // Double `?` to handle both Result from `spawn_blocking` and the actual function result.
-    
-        Ok(signatures)
+
+        let accounts = self.call("getProgramAccounts", |client| {
+            let config = config.clone();
+            async move { client.get_program_accounts_with_config(&program_id, config).await }
+        }).await.map_err(|e| e.to_string())?;
+
+        Ok(accounts.len() as u64)
+    }
+
+    // Function to fetch a single transaction with its status meta, including inner instructions and logs
+    pub async fn get_transaction(
+        &self,
+        signature: &str,
+    ) -> Result<EncodedConfirmedTransactionWithStatusMeta, Box<dyn Error + Send + Sync>> {
+        let signature = Signature::from_str(signature)?;
+        let config = RpcTransactionConfig {
+            encoding: Some(UiTransactionEncoding::Base64),
+            commitment: Some(CommitmentConfig::confirmed()),
+            max_supported_transaction_version: Some(0),
+        };
+
+        let transaction = self.call("getTransaction", |client| async move {
+            client.get_transaction_with_config(&signature, config).await
+        }).await?;
+
+        Ok(transaction)
     }
-    
 
-    // // Function to parse transactions and their instructions for a given program_id
-    //  pub async fn parsed_instructions(&self, program_id: &str, db: Arc<GraphDatabase>,) -> Result<(), Box<dyn Error>> {
+    // Function to simulate a transaction for the inner instructions it would invoke.
+    // Signatures are not checked and the blockhash is replaced, so unsigned or expired transactions work too.
+    pub async fn simulate_transaction(&self, transaction: &VersionedTransaction) -> Result<RpcSimulateTransactionResult, String> {
+        let config = RpcSimulateTransactionConfig {
+            sig_verify: false,
+            replace_recent_blockhash: true,
+            commitment: Some(CommitmentConfig::confirmed()),
+            encoding: Some(UiTransactionEncoding::Base64),
+            inner_instructions: true,
+            ..RpcSimulateTransactionConfig::default()
+        };
 
-    //     let signatures = self.get_transactions(program_id)?;
+        self.call("simulateTransaction", |client| {
+            let transaction = transaction.clone();
+            let config = config.clone();
+            async move {
+                client
+                    .simulate_transaction_with_config(&transaction, config)
+                    .await
+                    .map(|response| response.value)
+            }
+        }).await.map_err(|e| e.to_string())
+    }
 
-    //     for signature in signatures {
-    //         let tx_signature = Signature::from_str(&signature.signature)?;
-    //         let transaction_result = self.client.get_transaction(&tx_signature, UiTransactionEncoding::Json)?;
+    // Function to fetch the signatures of a program newer than `until`, oldest first.
+    // Pages back through the history until `until` is reached; without `until` only the newest page is
+    // fetched, so a first run does not walk the program's whole history.
+    pub async fn get_transactions_since(
+        &self,
+        program_id: &str,
+        until: Option<&str>,
+    ) -> Result<Vec<RpcConfirmedTransactionStatusWithSignature>, Box<dyn Error + Send + Sync>> {
+        let program_pubkey = Pubkey::from_str(program_id)?;
+        let until = until.map(Signature::from_str).transpose()?;
 
-    //         let transaction = transaction_result.transaction.transaction.decode().unwrap();
-    //         let instructions = transaction.message.instructions();
-    //         for instruction in instructions{
-    //             let program_id = instruction.program_id_index.to_string();
-    //             let accounts = instruction.accounts.clone();
-    //             let data = instruction.data.clone();
-    //             // // let discriminator = &data[0..8];
-    //             // let discriminator_data: Value = serde_json::from_slice(&data[0..8])?;
-    //             // let instruction_data: Value = serde_json::from_slice(&data[8..])?;
+        let mut signatures = Vec::new();
+        let mut before = None;
+        loop {
+            let page = self.call("getSignaturesForAddress", |client| async move {
+                client.get_signatures_for_address_with_config(
+                    &program_pubkey,
+                    GetConfirmedSignaturesForAddress2Config {
+                        before,
+                        until,
+                        limit: Some(SIGNATURE_PAGE_SIZE),
+                        ..GetConfirmedSignaturesForAddress2Config::default()
+                    },
+                ).await
+            }).await?;
 
-    //             let discriminator_data = data[0..8].to_vec();
-    //             let instruction_data = data[8..].to_vec();
+            let full = page.len() == SIGNATURE_PAGE_SIZE;
+            before = page.last().map(|signature| Signature::from_str(&signature.signature)).transpose()?;
+            signatures.extend(page);
 
-    //             // Extract user information from accounts or signers
-    //             let contributing_account = accounts.get(0).unwrap(); 
+            if !full || until.is_none() {
+                break;
+            }
+        }
 
-    //             let user_id = contributing_account.to_string();
-    //             db.upload_discriminator(
-    //                 &program_id, 
-    //                 discriminator_data, 
-    //                 instruction_data, 
-    //                 &user_id // Use user ID from accounts or signers
-    //             ).await?;
+        // The RPC returns newest first; ingest in the order the transactions landed
+        signatures.reverse();
+        Ok(signatures)
+    }
 
-    //         }
+    // Function to fetch a transaction by signature and store the discriminators it executed
+    pub async fn process_signature<S: ListenerSink>(
+        &self,
+        sink: &S,
+        program_id: &str,
+        signature: &str,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let transaction = self.get_transaction(signature).await
+            .map_err(|e| format!("Failed to get transaction {}: {}", signature, e))?;
+        sink.store_transaction(program_id, signature, &transaction).await
+            .map_err(|e| format!("Failed to store transaction {}: {}", signature, e))?;
+        Ok(())
+    }
 
-    //     }
+    // Function to ingest every transaction that landed after `last_signature`, oldest first.
+    // `last_signature` is moved past each transaction once it is stored. Stops at the first one that
+    // fails, so the next attempt starts over from it instead of skipping it.
+    async fn backfill<S: ListenerSink>(
+        &self,
+        sink: &S,
+        program_id: &str,
+        last_signature: &mut Option<String>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let signatures = self.get_transactions_since(program_id, last_signature.as_deref()).await?;
 
-    //     Ok(())
-    // }
-    
+        for signature in signatures {
+            self.process_signature(sink, program_id, &signature.signature).await?;
+            *last_signature = Some(signature.signature);
+        }
 
-    pub async fn real_time_listener(
+        Ok(())
+    }
+
+    // Function to run the listener for a program in the given mode
+    pub async fn run_listener<S: ListenerSink>(
+        &self,
+        mode: ListenerMode,
+        sink: Arc<S>,
+        program_id: String,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        match mode {
+            ListenerMode::Polling => self.real_time_listener(sink, program_id).await,
+            ListenerMode::WebSocket => self.websocket_listener(sink, program_id).await,
+        }
+    }
+
+    pub async fn real_time_listener<S: ListenerSink>(
         &self, 
-        db: Arc<GraphDatabase>,
+        sink: Arc<S>,
         program_id: String,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut last_signature = None;
         loop {
-            match self.get_transactions(&program_id).await {
-                Ok(signatures) => {
-                    for signature in signatures {
-                        let tx_signature = match Signature::from_str(&signature.signature) {
-                            Ok(sig) => sig,
-                            Err(e) => {
-                                eprintln!("Failed to parse signature: {}", e);
-                                continue;
-                            }
-                        };
+            if let Err(e) = self.backfill(&*sink, &program_id, &mut last_signature).await {
+                eprintln!("Error fetching transactions: {:?}", e);
+            }
 
-                        let transaction_result = match self.client.get_transaction(&tx_signature, UiTransactionEncoding::Json) {
-                            Ok(result) => result,
-                            Err(e) => {
-                                eprintln!("Failed to get transaction: {}", e);
-                                continue;
-                            }
-                        };
-
-                        // Decode and process the transaction
-                        if let Some(transaction) = transaction_result.transaction.transaction.decode() {
-                            let instructions = transaction.message.instructions();
-
-                            for instruction in instructions {
-                                let program_id = instruction.program_id_index.to_string();
-                                let accounts = instruction.accounts.clone();
-                                let data = instruction.data.clone();
-                                let discriminator_data = data[0..8].to_vec();
-                                let instruction_data = data[8..].to_vec();
-                                let user_id = accounts.get(0).unwrap().to_string();
-
-                                // Store the extracted data in the database
-                                if let Err(e) = db.upload_discriminator(
-                                    &program_id,
-                                    discriminator_data,
-                                    instruction_data,
-                                    &user_id,
-                                ).await {
-                                    eprintln!("Failed to store transaction data: {}", e);
-                                }
-                            }
-                        }
+            tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
+        }
+    }
+
+    // Listener driven by the PubSub `logsSubscribe` and `programSubscribe` feeds.
+    // After a disconnect it reconnects with backoff and fills the gap through the polling path.
+    // The backoff only starts over once a connection has delivered data, so a server that keeps
+    // closing connections straight away is retried less and less often.
+    pub async fn websocket_listener<S: ListenerSink>(
+        &self,
+        sink: Arc<S>,
+        program_id: String,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let program_pubkey = Pubkey::from_str(&program_id)?;
+        let mut last_signature = None;
+        let mut backoff = INITIAL_RECONNECT_BACKOFF;
+
+        loop {
+            // Catch up on anything that landed while we were not subscribed
+            if let Err(e) = self.backfill(&*sink, &program_id, &mut last_signature).await {
+                eprintln!("Gap-fill failed for {}: {:?}", program_id, e);
+            }
+
+            let mut delivered = false;
+            let outcome = self
+                .stream_program_events(&*sink, &program_id, &program_pubkey, &mut last_signature, &mut delivered)
+                .await;
+            if delivered {
+                backoff = INITIAL_RECONNECT_BACKOFF;
+            }
+
+            match outcome {
+                Ok(()) => eprintln!("PubSub stream for {} closed, reconnecting in {:?}", program_id, backoff),
+                Err(e) => eprintln!("PubSub stream for {} failed: {}, reconnecting in {:?}", program_id, e, backoff),
+            }
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
+        }
+    }
+
+    // Function to consume the PubSub feeds for a program until the connection drops.
+    // `delivered` is set once either feed delivers a notification.
+    async fn stream_program_events<S: ListenerSink>(
+        &self,
+        sink: &S,
+        program_id: &str,
+        program_pubkey: &Pubkey,
+        last_signature: &mut Option<String>,
+        delivered: &mut bool,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // Subscribe through the healthiest endpoint at the time of (re)connecting
+        let endpoint = self.endpoints_by_health()[0];
+        let pubsub = PubsubClient::new(&endpoint.ws_url).await?;
+
+        let (mut logs, logs_unsubscribe) = pubsub.logs_subscribe(
+            RpcTransactionLogsFilter::Mentions(vec![program_id.to_string()]),
+            RpcTransactionLogsConfig { commitment: Some(CommitmentConfig::confirmed()) },
+        ).await?;
+
+        let account_config = RpcProgramAccountsConfig {
+            account_config: RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                data_slice: Some(UiDataSliceConfig { offset: 0, length: 8 }),
+                commitment: Some(CommitmentConfig::confirmed()),
+                ..RpcAccountInfoConfig::default()
+            },
+            ..RpcProgramAccountsConfig::default()
+        };
+        let (mut accounts, accounts_unsubscribe) = pubsub.program_subscribe(program_pubkey, Some(account_config)).await?;
+
+        info!("Subscribed to PubSub feeds for {} via {}", program_id, endpoint.ws_url);
+
+        // A transaction that fails to store ends the subscription without moving `last_signature`,
+        // so the gap-fill after reconnecting retries it
+        let mut failure = None;
+        loop {
+            tokio::select! {
+                notification = logs.next() => {
+                    let Some(notification) = notification else { break };
+                    *delivered = true;
+                    let signature = notification.value.signature;
+                    if let Err(e) = self.process_signature(sink, program_id, &signature).await {
+                        failure = Some(e);
+                        break;
                     }
+                    *last_signature = Some(signature);
                 }
-                Err(e) => eprintln!("Error fetching transactions: {:?}", e),
-            }
+                notification = accounts.next() => {
+                    let Some(notification) = notification else { break };
+                    *delivered = true;
+                    let keyed = notification.value;
+                    let Some(account) = keyed.account.decode::<Account>() else { continue };
+                    let Some((discriminator_data, _)) = split_discriminator(&account.data) else { continue };
 
-            tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
+                    if let Err(e) = sink.store_account(program_id, &keyed.pubkey, discriminator_data).await {
+                        eprintln!("Failed to store account data: {}", e);
+                    }
+                }
+            }
         }
+
+        logs_unsubscribe().await;
+        accounts_unsubscribe().await;
+        failure.map_or(Ok(()), Err)
     }
-    
+}
+
+// Number of accounts of a program that start with the same discriminator
+#[derive(Debug, Clone, Serialize)]
+pub struct AccountDiscriminatorCount {
+    pub discriminator: Vec<u8>,
+    pub count: u64,
+    // Distinct data lengths of those accounts in ascending order, so a type is identified by prefix plus length
+    pub spaces: Vec<u64>,
+    pub sample_account: String,
+}
 
-    
+// How a listener learns about new transactions for a program
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ListenerMode {
+    Polling,
+    WebSocket,
+}
 
+// Function to decide whether an RPC error is worth retrying on another endpoint
+fn is_transient(error: &ClientError) -> bool {
+    match error.kind() {
+        ClientErrorKind::Io(_) | ClientErrorKind::Reqwest(_) => true,
+        ClientErrorKind::RpcError(RpcError::RpcRequestError(_)) => true,
+        // Node is behind or unhealthy
+        ClientErrorKind::RpcError(RpcError::RpcResponseError { code, .. }) => *code == -32005,
+        _ => false,
+    }
+}
 
-    
+// Function to derive the PubSub endpoint from an RPC endpoint (https -> wss, http -> ws)
+fn websocket_url_for(url: &str) -> String {
+    if let Some(rest) = url.strip_prefix("https://") {
+        format!("wss://{}", rest)
+    } else if let Some(rest) = url.strip_prefix("http://") {
+        format!("ws://{}", rest)
+    } else {
+        url.to_string()
+    }
 }
 
 // Implement the Clone trait manually
 impl Clone for SolanaConnection {
     fn clone(&self) -> Self {
         SolanaConnection {
-            client: Arc::clone(&self.client),
+            endpoints: Arc::clone(&self.endpoints),
+            budget: Arc::clone(&self.budget),
+            priority: self.priority,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+    use serde_json::{json, Value};
+    use std::sync::Mutex;
+
+    const PROGRAM_ID: &str = "11111111111111111111111111111111";
+    const SIGNATURE: &str = "5VERv8NMvzbJMEkV8xnrLkEaWRtSz9CosKDYjCJjBRnbJLgp8uirBgmQpjKhoR4tjF3ZpRzrFmBV6UjKdiSZkQUW";
+
+    // What the fake node has been asked
+    #[derive(Default)]
+    struct FakeNode {
+        connections: usize,
+        transactions_requested: usize,
+        signature_queries: Vec<Option<String>>,
+        // Returned for every signature query, newest first
+        signatures: Vec<String>,
+    }
+
+    type SharedNode = web::Data<Mutex<FakeNode>>;
+
+    // Answers the JSON-RPC calls the listener makes. Every transaction is an empty one without metadata.
+    async fn fake_rpc(node: SharedNode, request: web::Json<Value>) -> HttpResponse {
+        let id = request["id"].clone();
+        let response = match request["method"].as_str() {
+            Some("getSignaturesForAddress") => {
+                let until = request["params"][1]["until"].as_str().map(str::to_string);
+                let mut node = node.lock().unwrap();
+                node.signature_queries.push(until);
+                let signatures: Vec<Value> = node
+                    .signatures
+                    .iter()
+                    .map(|signature| json!({"signature": signature, "slot": 1, "err": null, "memo": null, "blockTime": null}))
+                    .collect();
+                json!({"jsonrpc": "2.0", "result": signatures, "id": id})
+            }
+            Some("getTransaction") => {
+                node.lock().unwrap().transactions_requested += 1;
+                json!({"jsonrpc": "2.0", "result": {"slot": 1, "transaction": "", "meta": null, "blockTime": null}, "id": id})
+            }
+            _ => json!({"jsonrpc": "2.0", "error": {"code": -32601, "message": "Method not found"}, "id": id}),
+        };
+        HttpResponse::Ok().json(response)
+    }
+
+    // Answers subscriptions over PubSub. The first connection delivers one log notification and is
+    // dropped once the listener has looked the transaction up; later connections stay open.
+    async fn fake_pubsub(node: SharedNode, req: HttpRequest, body: web::Payload) -> actix_web::Result<HttpResponse> {
+        let (response, mut session, mut messages) = actix_ws::handle(&req, body)?;
+        let connection = {
+            let mut node = node.lock().unwrap();
+            node.connections += 1;
+            node.connections
+        };
+
+        actix_web::rt::spawn(async move {
+            let mut subscriptions = 0;
+            while let Some(Ok(actix_ws::Message::Text(text))) = messages.next().await {
+                let request: Value = serde_json::from_str(&text).unwrap();
+                let method = request["method"].as_str().unwrap_or_default();
+                if !method.ends_with("Subscribe") {
+                    continue;
+                }
+                subscriptions += 1;
+                let subscription = if method == "logsSubscribe" { 1 } else { 2 };
+                let ack = json!({"jsonrpc": "2.0", "result": subscription, "id": request["id"]});
+                session.text(ack.to_string()).await.unwrap();
+
+                if connection == 1 && subscriptions == 2 {
+                    let notification = json!({
+                        "jsonrpc": "2.0",
+                        "method": "logsNotification",
+                        "params": {
+                            "result": {
+                                "context": {"slot": 1},
+                                "value": {"signature": SIGNATURE, "err": null, "logs": []},
+                            },
+                            "subscription": 1,
+                        },
+                    });
+                    session.text(notification.to_string()).await.unwrap();
+
+                    while node.lock().unwrap().transactions_requested == 0 {
+                        tokio::time::sleep(Duration::from_millis(10)).await;
+                    }
+                    let _ = session.close(None).await;
+                    return;
+                }
+            }
+        });
+
+        Ok(response)
+    }
+
+    struct NullSink;
+
+    impl ListenerSink for NullSink {
+        async fn store_transaction(
+            &self,
+            _program_id: &str,
+            _signature: &str,
+            _transaction: &EncodedConfirmedTransactionWithStatusMeta,
+        ) -> Result<(), Box<dyn Error + Send + Sync>> {
+            Ok(())
         }
+
+        async fn store_account(
+            &self,
+            _program_id: &str,
+            _account: &str,
+            _discriminator: Vec<u8>,
+        ) -> Result<(), Box<dyn Error + Send + Sync>> {
+            Ok(())
+        }
+    }
+
+    // Fails to store one transaction, and stores everything else
+    struct FailingSink {
+        signature: String,
+    }
+
+    impl ListenerSink for FailingSink {
+        async fn store_transaction(
+            &self,
+            _program_id: &str,
+            signature: &str,
+            _transaction: &EncodedConfirmedTransactionWithStatusMeta,
+        ) -> Result<(), Box<dyn Error + Send + Sync>> {
+            if signature == self.signature {
+                return Err("database unavailable".into());
+            }
+            Ok(())
+        }
+
+        async fn store_account(
+            &self,
+            _program_id: &str,
+            _account: &str,
+            _discriminator: Vec<u8>,
+        ) -> Result<(), Box<dyn Error + Send + Sync>> {
+            Ok(())
+        }
+    }
+
+    // Function to serve the fake node on a free port and return its URL
+    fn serve(node: SharedNode) -> String {
+        let server = HttpServer::new(move || {
+            App::new().app_data(node.clone()).service(
+                web::resource("/")
+                    .route(web::post().to(fake_rpc))
+                    .route(web::get().to(fake_pubsub)),
+            )
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let url = format!("http://{}", server.addrs()[0]);
+        actix_web::rt::spawn(server.run());
+        url
+    }
+
+    #[actix_web::test]
+    async fn backfill_stops_at_the_first_transaction_that_fails_to_store() {
+        let oldest = Signature::from([1u8; 64]).to_string();
+        let failing = Signature::from([2u8; 64]).to_string();
+        let newest = Signature::from([3u8; 64]).to_string();
+        let node = web::Data::new(Mutex::new(FakeNode {
+            signatures: vec![newest, failing.clone(), oldest.clone()],
+            ..FakeNode::default()
+        }));
+        let connection = SolanaConnection::new(&[&serve(node.clone())], 100.0);
+
+        let mut last_signature = None;
+        let sink = FailingSink { signature: failing };
+        let result = connection.backfill(&sink, PROGRAM_ID, &mut last_signature).await;
+
+        assert!(result.is_err());
+        assert_eq!(last_signature, Some(oldest));
+        assert_eq!(node.lock().unwrap().transactions_requested, 2);
+    }
+
+    #[actix_web::test]
+    async fn websocket_listener_reconnects_and_fills_the_gap() {
+        let node = web::Data::new(Mutex::new(FakeNode::default()));
+        let url = serve(node.clone());
+
+        let connection = Arc::new(SolanaConnection::new(&[&url], 100.0));
+        let listener = tokio::spawn(async move {
+            connection.websocket_listener(Arc::new(NullSink), PROGRAM_ID.to_string()).await
+        });
+
+        let reconnected = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                {
+                    let node = node.lock().unwrap();
+                    let gap_filled = node.signature_queries.iter().any(|until| until.as_deref() == Some(SIGNATURE));
+                    if node.connections >= 2 && gap_filled {
+                        break;
+                    }
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await;
+        listener.abort();
+
+        let node = node.lock().unwrap();
+        assert!(reconnected.is_ok(), "listener did not reconnect and gap-fill, saw {} connections", node.connections);
+        // The first gap-fill has nothing to resume from, the one after the reconnect starts at the streamed signature
+        assert_eq!(node.signature_queries[0], None);
+        assert_eq!(node.transactions_requested, 1);
     }
 }
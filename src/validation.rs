@@ -0,0 +1,97 @@
+use actix_web::dev::Payload;
+use actix_web::error::{InternalError, JsonPayloadError, QueryPayloadError};
+use actix_web::{web, FromRequest, HttpRequest, HttpResponse};
+use futures::future::{ready, Ready};
+use serde_json::json;
+use solana_sdk::pubkey::Pubkey;
+use std::str::FromStr;
+
+// Largest body, JSON or raw, accepted by routes that do not set their own limit.
+// Uploads with an IDL layout are the biggest legitimate payloads.
+pub const MAX_PAYLOAD_SIZE: usize = 64 * 1024;
+
+// Function to check that a string is a base58 encoded 32 byte public key
+pub fn is_valid_pubkey(value: &str) -> bool {
+    Pubkey::from_str(value).is_ok()
+}
+
+// Function to turn a rejected input into a `400 Bad Request` with the usual `{"error": ...}` body
+fn bad_request(message: String) -> actix_web::Error {
+    let response = HttpResponse::BadRequest().json(json!({"error": message}));
+    InternalError::from_response(message, response).into()
+}
+
+// Function to read a named path segment, which the route definitions guarantee to exist
+fn path_segment(req: &HttpRequest, name: &str) -> Result<String, actix_web::Error> {
+    req.match_info()
+        .get(name)
+        .map(str::to_string)
+        .ok_or_else(|| bad_request(format!("Missing {}", name)))
+}
+
+// A `{program_id}` path segment that is known to be a valid pubkey
+#[derive(Debug, Clone)]
+pub struct ProgramId(String);
+
+impl ProgramId {
+    pub fn into_inner(self) -> String {
+        self.0
+    }
+}
+
+impl FromRequest for ProgramId {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(path_segment(req, "program_id").and_then(|program_id| {
+            if is_valid_pubkey(&program_id) {
+                Ok(ProgramId(program_id))
+            } else {
+                Err(bad_request(format!("Invalid program_id: {}", program_id)))
+            }
+        }))
+    }
+}
+
+// A `{discriminator}` path segment that decodes to exactly 8 hex-encoded bytes
+#[derive(Debug, Clone)]
+pub struct DiscriminatorParam {
+    pub hex: String,
+    pub bytes: Vec<u8>,
+}
+
+impl FromRequest for DiscriminatorParam {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(path_segment(req, "discriminator").and_then(|hex| match hex::decode(&hex) {
+            Ok(bytes) if bytes.len() == 8 => Ok(DiscriminatorParam { hex: hex.to_lowercase(), bytes }),
+            _ => Err(bad_request("Discriminator must be 8 hex-encoded bytes".to_string())),
+        }))
+    }
+}
+
+// JSON extractor settings shared by every route: a size limit and JSON error bodies
+pub fn json_config() -> web::JsonConfig {
+    web::JsonConfig::default()
+        .limit(MAX_PAYLOAD_SIZE)
+        .error_handler(|err, _req| {
+            let response = match &err {
+                JsonPayloadError::Overflow { .. } | JsonPayloadError::OverflowKnownLength { .. } => {
+                    HttpResponse::PayloadTooLarge().json(json!({"error": err.to_string(), "limit": MAX_PAYLOAD_SIZE}))
+                }
+                _ => HttpResponse::BadRequest().json(json!({"error": err.to_string()})),
+            };
+            InternalError::from_response(err, response).into()
+        })
+}
+
+// Query string extractor settings, so bad filters get a JSON error like everything else
+pub fn query_config() -> web::QueryConfig {
+    web::QueryConfig::default().error_handler(|err: QueryPayloadError, _req| {
+        let response = HttpResponse::BadRequest().json(json!({"error": err.to_string()}));
+        InternalError::from_response(err, response).into()
+    })
+}